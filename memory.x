@@ -1,5 +1,7 @@
 MEMORY
 {
-    FLASH : ORIGIN = 0x08000000, LENGTH = 64K
-    RAM   : ORIGIN = 0x20000000, LENGTH = 20K
+    FLASH   : ORIGIN = 0x08000000, LENGTH = 60K
+    /* settings, see src/storage.rs */
+    STORAGE : ORIGIN = 0x0800F000, LENGTH = 4K
+    RAM     : ORIGIN = 0x20000000, LENGTH = 20K
 }
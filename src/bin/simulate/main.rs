@@ -0,0 +1,200 @@
+//! Replays MIDI through the modes on the host and writes a CSV or VCD trace of the outputs.
+//!
+//! `cargo run --no-default-features --features host --target x86_64-unknown-linux-gnu
+//! --bin simulate -- [options] <input> [output]`
+//!
+//! The input is either a Standard MIDI File or a raw MIDI byte stream, which is paced at the
+//! speed of the serial port. The trace is written to stdout without an output path.
+
+#![allow(clippy::needless_return)]
+
+use smf::Event;
+use trace::{Format, Trace};
+
+use etas_midi2cv_firmware::mock::MockOutputs;
+use etas_midi2cv_firmware::modes::*;
+use etas_midi2cv_firmware::settings::Settings;
+use etas_midi2cv_firmware::tuning::{MtsReceiver, Selector};
+
+use embedded_midi::MidiParser;
+use fugit::MicrosDurationU32;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process;
+
+const USAGE: &str = "usage: simulate [--mode NAME] [--step US] [--tail MS] [--csv | --vcd] \
+                     <input.mid | input.bin> [output]";
+
+const N_MODES: usize = 7;
+const MODE_NAMES: [&str; N_MODES] = ["mono", "poly", "duo", "drum", "multi", "split", "arp"];
+
+/// Time a byte takes at 31250 baud with start and stop bits.
+const BYTE_TIME_US: u64 = 320;
+
+struct Options {
+    mode: usize,
+    step: u32,
+    tail: u64,
+    format: Option<Format>,
+    input: String,
+    output: Option<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options {
+            mode: 0,
+            step: 50,
+            tail: 100_000,
+            format: None,
+            input: String::new(),
+            output: None,
+        };
+        let mut paths = Vec::new();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            match arg.as_str() {
+                "--mode" => {
+                    let name = value()?;
+                    options.mode = MODE_NAMES
+                        .iter()
+                        .position(|&mode| mode == name)
+                        .ok_or(format!("unknown mode {}", name))?;
+                },
+                "--step" => {
+                    options.step = value()?.parse().map_err(|_| "invalid step")?;
+                    if options.step == 0 {
+                        return Err("step must not be zero".into());
+                    }
+                },
+                "--tail" => {
+                    let tail: u64 = value()?.parse().map_err(|_| "invalid tail")?;
+                    options.tail = tail * 1000;
+                },
+                "--csv" => options.format = Some(Format::Csv),
+                "--vcd" => options.format = Some(Format::Vcd),
+                _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+                _ => paths.push(arg),
+            }
+        }
+        let mut paths = paths.into_iter();
+        options.input = paths.next().ok_or("missing input")?;
+        options.output = paths.next();
+        if paths.next().is_some() {
+            return Err("too many arguments".into());
+        }
+        return Ok(options);
+    }
+
+    fn format(&self) -> Format {
+        return match (self.format, &self.output) {
+            (Some(format), _) => format,
+            (None, Some(output)) if output.ends_with(".vcd") => Format::Vcd,
+            _ => Format::Csv,
+        };
+    }
+}
+
+fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            process::exit(2);
+        },
+    };
+    if let Err(error) = run(&options) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let data = fs::read(&options.input).map_err(|e| format!("{}: {}", options.input, e))?;
+    let events = if smf::is_smf(&data) {
+        smf::parse(&data).map_err(|e| format!("{}: {}", options.input, e))?
+    }
+    else {
+        let time = |i| i as u64 * BYTE_TIME_US;
+        data.iter().enumerate().map(|(i, &b)| Event { time: time(i), bytes: vec![b] }).collect()
+    };
+
+    let trace = simulate(&events, options);
+
+    let result = match &options.output {
+        Some(path) => File::create(path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            trace.write(options.format(), &mut writer)?;
+            return writer.flush();
+        }),
+        None => trace.write(options.format(), &mut io::stdout().lock()),
+    };
+    return result.map_err(|e| format!("writing trace: {}", e));
+}
+
+/// Runs the same steps as the main loop of the firmware, in steps of simulated time.
+fn simulate(events: &[Event], options: &Options) -> Trace {
+    let mut settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+
+    let mut mono = Mono::default();
+    let mut poly = Poly::default();
+    let mut duo = Duo::default();
+    let mut drum = Drum::default();
+    let mut multi = Multi::default();
+    let mut split = Split::default();
+    let mut arp = Arp::default();
+    let mut modes: [&mut dyn Mode<MockOutputs>; N_MODES] =
+        [&mut mono, &mut poly, &mut duo, &mut drum, &mut multi, &mut split, &mut arp];
+    let mode = &mut modes[options.mode];
+
+    let mut clock = Clock::default();
+    clock.set_gates(mode.clock_gates(), &mut outputs);
+
+    let mut parser = MidiParser::new();
+    let mut selector = Selector::default();
+    let mut parameters = Parameters::default();
+    let mut mts = MtsReceiver::default();
+    let mut trace = Trace::default();
+    let end = events.last().map_or(0, |event| event.time) + options.tail;
+    let step = MicrosDurationU32::micros(options.step);
+
+    let mut events = events.iter().peekable();
+    let mut time = 0;
+    loop {
+        while let Some(event) = events.next_if(|event| event.time <= time) {
+            for &byte in &event.bytes {
+                mts.parse_byte(byte, outputs.pitches_mut().table_mut());
+                if let Some(message) = parser.parse_byte(byte) {
+                    if selector.handle_midi_event(message, &mut settings.tuning) {
+                        outputs.pitches_mut().set_tuning(settings.tuning);
+                    }
+                    parameters.handle_midi_event(
+                        message,
+                        &mut **mode,
+                        &mut outputs,
+                        &mut settings,
+                    );
+                    clock.handle_midi_event(message, &mut outputs, &settings);
+                    handle_channel_mode(message, &mut **mode, &mut outputs, &mut settings);
+                    mode.handle_midi_event(message, &mut outputs, &settings);
+                }
+            }
+        }
+        trace.record(time, outputs.take_events());
+        if time >= end {
+            return trace;
+        }
+        time += options.step as u64;
+        mode.update(step, &mut outputs);
+        clock.update(step, &mut outputs);
+    }
+}
+
+extern crate embedded_midi;
+extern crate etas_midi2cv_firmware;
+extern crate fugit;
+
+mod smf;
+mod trace;
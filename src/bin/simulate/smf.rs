@@ -0,0 +1,169 @@
+//! Minimal Standard MIDI File reader, merging all tracks into one stream of timed messages.
+
+const DEFAULT_TEMPO: u64 = 500_000; // 120 bpm in microseconds per quarter note
+
+/// MIDI bytes to send at a time in microseconds.
+#[derive(Debug)]
+pub struct Event {
+    pub time: u64,
+    pub bytes: Vec<u8>,
+}
+
+pub fn is_smf(data: &[u8]) -> bool {
+    return data.starts_with(b"MThd");
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<Event>, String> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.bytes(4)? != b"MThd" {
+        return Err("missing header chunk".into());
+    }
+    let header_len = reader.u32()? as usize;
+    let header = reader.bytes(header_len)?;
+    if header.len() < 6 {
+        return Err("header chunk too short".into());
+    }
+    let n_tracks = u16::from_be_bytes([header[2], header[3]]);
+    let division = u16::from_be_bytes([header[4], header[5]]);
+
+    let mut items = Vec::new();
+    let mut tracks = 0;
+    while tracks < n_tracks && reader.pos < data.len() {
+        let kind = reader.bytes(4)?;
+        let len = reader.u32()? as usize;
+        let chunk = reader.bytes(len)?;
+        if kind == b"MTrk" {
+            read_track(chunk, &mut items)?;
+            tracks += 1;
+        }
+    }
+    // stable, so events at the same tick keep their order within and across tracks
+    items.sort_by_key(|item| item.0);
+    return Ok(to_events(items, division));
+}
+
+enum Item {
+    Midi(Vec<u8>),
+    Tempo(u64),
+}
+
+fn read_track(data: &[u8], items: &mut Vec<(u64, Item)>) -> Result<(), String> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut tick = 0;
+    let mut running_status = None;
+    while reader.pos < data.len() {
+        tick += reader.vlq()? as u64;
+        let mut status = reader.u8()?;
+        let mut first = None;
+        if status < 0x80 {
+            first = Some(status);
+            status = running_status.ok_or("data byte without running status")?;
+        }
+        match status {
+            0xff => {
+                running_status = None;
+                let kind = reader.u8()?;
+                let len = reader.vlq()? as usize;
+                let meta = reader.bytes(len)?;
+                match kind {
+                    0x2f => break,
+                    0x51 if len == 3 => {
+                        let tempo = u32::from_be_bytes([0, meta[0], meta[1], meta[2]]);
+                        items.push((tick, Item::Tempo(tempo as u64)));
+                    },
+                    _ => (),
+                }
+            },
+            0xf0 | 0xf7 => {
+                running_status = None;
+                let len = reader.vlq()? as usize;
+                let mut bytes = Vec::with_capacity(len + 1);
+                if status == 0xf0 {
+                    bytes.push(0xf0);
+                }
+                bytes.extend_from_slice(reader.bytes(len)?);
+                items.push((tick, Item::Midi(bytes)));
+            },
+            0x80..=0xef => {
+                running_status = Some(status);
+                let n_data = if matches!(status & 0xf0, 0xc0 | 0xd0) { 1 } else { 2 };
+                let mut bytes = Vec::with_capacity(3);
+                bytes.push(status);
+                if let Some(first) = first {
+                    bytes.push(first);
+                }
+                while bytes.len() <= n_data {
+                    bytes.push(reader.u8()?);
+                }
+                items.push((tick, Item::Midi(bytes)));
+            },
+            _ => return Err(format!("unexpected status byte {:#04x}", status)),
+        }
+    }
+    return Ok(());
+}
+
+fn to_events(items: Vec<(u64, Item)>, division: u16) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut tempo = DEFAULT_TEMPO;
+    let mut anchor_tick = 0;
+    let mut anchor_time = 0;
+    let time = |tick: u64, tempo: u64, anchor_tick: u64, anchor_time: u64| {
+        if division & 0x8000 != 0 {
+            // SMPTE timing: negative frames per second in the high byte, ticks per frame
+            let fps = -((division >> 8) as i8) as u64;
+            let ticks_per_frame = (division & 0xff) as u64;
+            return tick * 1_000_000 / (fps * ticks_per_frame).max(1);
+        }
+        let ticks_per_quarter = (division as u64).max(1);
+        return anchor_time + (tick - anchor_tick) * tempo / ticks_per_quarter;
+    };
+    for (tick, item) in items {
+        match item {
+            Item::Tempo(new_tempo) => {
+                anchor_time = time(tick, tempo, anchor_tick, anchor_time);
+                anchor_tick = tick;
+                tempo = new_tempo;
+            },
+            Item::Midi(bytes) => {
+                events.push(Event { time: time(tick, tempo, anchor_tick, anchor_time), bytes });
+            },
+        }
+    }
+    return events;
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or("unexpected end of file")?;
+        self.pos += len;
+        return Ok(bytes);
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        return Ok(self.bytes(1)?[0]);
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        return Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+
+    /// Variable length quantity, 7 bits per byte with the high bit set on all but the last.
+    fn vlq(&mut self) -> Result<u32, String> {
+        let mut value = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        return Err("variable length quantity too long".into());
+    }
+}
@@ -0,0 +1,121 @@
+use etas_midi2cv_firmware::io::{N_CVS, N_GATES};
+use etas_midi2cv_firmware::mock::Event;
+
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Csv,
+    Vcd,
+}
+
+/// Gate and CV output changes over time, starting with all gates closed at 0V.
+#[derive(Default, Debug)]
+pub struct Trace {
+    changes: Vec<(u64, Event)>,
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    gates: [bool; N_GATES],
+    voltages: [f64; N_CVS],
+}
+
+impl State {
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Gate(gate, value) => self.gates[gate as usize] = value,
+            Event::Cv(cv, voltage) => self.voltages[cv as usize] = voltage.to_num(),
+        }
+    }
+}
+
+impl Trace {
+    pub fn record(&mut self, time: u64, events: Vec<Event>) {
+        self.changes.extend(events.into_iter().map(|event| (time, event)));
+    }
+
+    pub fn write<W: Write>(&self, format: Format, writer: &mut W) -> io::Result<()> {
+        return match format {
+            Format::Csv => self.write_csv(writer),
+            Format::Vcd => self.write_vcd(writer),
+        };
+    }
+
+    /// One row with the state of every output per point in time at which any of them changed.
+    fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "time_us")?;
+        for i in 0..N_GATES {
+            write!(writer, ",gate{}", i + 1)?;
+        }
+        for i in 0..N_CVS {
+            write!(writer, ",cv{}", i + 1)?;
+        }
+        writeln!(writer)?;
+
+        let mut state = State { gates: [false; N_GATES], voltages: [0.0; N_CVS] };
+        let mut changes = self.changes.iter().peekable();
+        let mut time = 0;
+        loop {
+            while let Some(&&(_, event)) = changes.peek().filter(|change| change.0 == time) {
+                state.apply(event);
+                changes.next();
+            }
+            write!(writer, "{}", time)?;
+            for gate in state.gates {
+                write!(writer, ",{}", gate as u8)?;
+            }
+            for voltage in state.voltages {
+                write!(writer, ",{:.4}", voltage)?;
+            }
+            writeln!(writer)?;
+            time = match changes.peek() {
+                Some(change) => change.0,
+                None => return Ok(()),
+            };
+        }
+    }
+
+    /// Value change dump with a 1us timescale, gates as wires and voltages as reals.
+    fn write_vcd<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let id = |i: usize| (b'!' + i as u8) as char;
+        writeln!(writer, "$timescale 1us $end")?;
+        writeln!(writer, "$scope module midi2cv $end")?;
+        for i in 0..N_GATES {
+            writeln!(writer, "$var wire 1 {} gate{} $end", id(i), i + 1)?;
+        }
+        for i in 0..N_CVS {
+            writeln!(writer, "$var real 64 {} cv{} $end", id(N_GATES + i), i + 1)?;
+        }
+        writeln!(writer, "$upscope $end")?;
+        writeln!(writer, "$enddefinitions $end")?;
+
+        writeln!(writer, "#0")?;
+        writeln!(writer, "$dumpvars")?;
+        for i in 0..N_GATES {
+            writeln!(writer, "0{}", id(i))?;
+        }
+        for i in 0..N_CVS {
+            writeln!(writer, "r0 {}", id(N_GATES + i))?;
+        }
+        writeln!(writer, "$end")?;
+
+        let mut last_time = 0;
+        for &(time, event) in &self.changes {
+            if time != last_time {
+                writeln!(writer, "#{}", time)?;
+                last_time = time;
+            }
+            match event {
+                Event::Gate(gate, value) => {
+                    writeln!(writer, "{}{}", value as u8, id(gate as usize))?;
+                },
+                Event::Cv(cv, voltage) => {
+                    let voltage: f64 = voltage.to_num();
+                    writeln!(writer, "r{} {}", voltage, id(N_GATES + cv as usize))?;
+                },
+            }
+        }
+        return Ok(());
+    }
+}
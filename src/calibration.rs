@@ -0,0 +1,132 @@
+//! Remote calibration over NRPN, so a script reading a voltmeter can tune a unit.
+//!
+//! Commands are only taken in the calibration menu, which is entered by holding the button at
+//! power on and outputs the selected level to be measured.
+//!
+//! Parameters are selected with NRPN MSB (CC 99) `0x40` and the NRPN LSB (CC 98) below. Values
+//! are 14 bit, sent as Data Entry MSB (CC 6) followed by Data Entry LSB (CC 38), and take
+//! effect with the LSB. Messages are accepted on every channel, replies go to the channel of
+//! the last command.
+//!
+//! | LSB | parameter                                           |
+//! |-----|-----------------------------------------------------|
+//! | 0   | select CV channel                                   |
+//! | 1   | select calibration level                            |
+//! | 2   | set the raw DAC code of the selected level          |
+//! | 3   | offset the raw DAC code, with 8192 as zero          |
+//! | 4   | send the calibration table back                     |
+//! | 5   | save the calibration                                |
+//! | 6   | fine-tune the selected channel in cents, 8192 as 0  |
+//! | 7   | pitch standard: 0 V/oct, 1 Buchla, 2 Hz/V, 3 custom |
+//! | 8   | root note of the selected channel                   |
+//! | 9   | custom octave size of the selected channel in mV    |
+//!
+//! The table is sent back as one NRPN per level, with `0x41 + channel` as MSB, the level as
+//! LSB and the raw DAC code as value.
+//!
+//! Per-semitone corrections are set with `0x48 + channel` as MSB, the semitone above the root
+//! note as LSB and the correction in cents as value, again with 8192 as zero. The corrected
+//! note is output, so it can be measured right away.
+
+use crate::context::Menu;
+use crate::io::N_CVS;
+use crate::midi::{
+    ParameterNumber, DATA_ENTRY_LSB_CC, DATA_ENTRY_MSB_CC, NRPN_LSB_CC, NRPN_MSB_CC,
+};
+
+use embedded_midi::MidiMessage as Midi;
+
+pub const NRPN_MSB: u8 = 0x40;
+pub const TABLE_NRPN_MSB: u8 = 0x41;
+pub const CORRECTION_NRPN_MSB: u8 = 0x48;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command {
+    SelectChannel(u8),
+    SelectLevel(u8),
+    Set(u16),
+    Offset(i16),
+    SendTable,
+    Save,
+    FineTune(i16),
+    Standard(u8),
+    Root(u8),
+    Millivolts(u16),
+    Correct { channel: u8, semitone: u8, cents: i16 },
+}
+
+#[derive(Debug)]
+pub struct Remote {
+    number: ParameterNumber,
+    value_msb: u8,
+    midi_channel: u8,
+}
+
+impl Default for Remote {
+    fn default() -> Self {
+        return Self { number: ParameterNumber::default(), value_msb: 0, midi_channel: 0 };
+    }
+}
+
+impl Remote {
+    pub fn handle_midi_event(&mut self, msg: Midi, menu: Menu) -> Option<Command> {
+        let (channel, cc, value) = match msg {
+            Midi::ControlChange(ch, cc, value) if menu.is_calibration() => {
+                (ch.into(), cc.into(), value.into())
+            },
+            _ => return None,
+        };
+        match cc {
+            DATA_ENTRY_MSB_CC => self.value_msb = value,
+            DATA_ENTRY_LSB_CC => {
+                let value = (self.value_msb as u16) << 7 | value as u16;
+                let command = self.number.nrpn().and_then(|nrpn| command(nrpn, value));
+                if command.is_some() {
+                    self.midi_channel = channel;
+                }
+                return command;
+            },
+            _ => {
+                self.number.handle_cc(cc, value);
+            },
+        }
+        return None;
+    }
+
+    /// The messages sending back the raw DAC code of one calibration level.
+    pub fn table_entry(&self, channel: u8, level: u8, value: u16) -> [Midi; 4] {
+        let cc = |cc: u8, value: u8| {
+            Midi::ControlChange(self.midi_channel.into(), cc.into(), (value & 0x7f).into())
+        };
+        return [
+            cc(NRPN_MSB_CC, TABLE_NRPN_MSB + channel),
+            cc(NRPN_LSB_CC, level),
+            cc(DATA_ENTRY_MSB_CC, (value >> 7) as u8),
+            cc(DATA_ENTRY_LSB_CC, value as u8),
+        ];
+    }
+}
+
+fn command((msb, lsb): (u8, u8), value: u16) -> Option<Command> {
+    let signed = value as i16 - 8192;
+    if msb == NRPN_MSB {
+        return match lsb {
+            0 if value < N_CVS as u16 => Some(Command::SelectChannel(value as u8)),
+            1 => Some(Command::SelectLevel(value.min(0xff) as u8)),
+            2 => Some(Command::Set(value)),
+            3 => Some(Command::Offset(signed)),
+            4 => Some(Command::SendTable),
+            5 => Some(Command::Save),
+            6 => Some(Command::FineTune(signed)),
+            7 => Some(Command::Standard(value.min(0xff) as u8)),
+            8 => Some(Command::Root(value.min(127) as u8)),
+            9 if value > 0 => Some(Command::Millivolts(value)),
+            _ => None,
+        };
+    }
+    else if (CORRECTION_NRPN_MSB..CORRECTION_NRPN_MSB + N_CVS as u8).contains(&msb) {
+        let channel = msb - CORRECTION_NRPN_MSB;
+        return Some(Command::Correct { channel, semitone: lsb, cents: signed });
+    }
+    return None;
+}
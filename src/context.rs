@@ -0,0 +1,43 @@
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Menu {
+    Main,
+    Calibration,
+    CalibrationEdit,
+    MidiLearn,
+    Settings,
+    SettingEdit,
+}
+
+impl Menu {
+    pub fn is_calibration(self) -> bool {
+        return matches!(self, Self::Calibration | Self::CalibrationEdit);
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Context {
+    pub menu: Menu,
+    pub mode: i8,
+    pub setting: i8,
+    pub cal_level: i8,
+    pub cal_channel: i8,
+    /// Raw DAC code offset of the calibration level being edited.
+    pub cal_offset: i16,
+    pub learn_slot: i8,
+    pub learn_hold: bool,
+}
+
+impl Context {
+    pub const fn new(default_menu: Menu) -> Self {
+        return Self {
+            menu: default_menu,
+            mode: 0,
+            setting: 0,
+            cal_level: 1,
+            cal_channel: 0,
+            cal_offset: 0,
+            learn_slot: 0,
+            learn_hold: false,
+        };
+    }
+}
@@ -0,0 +1,139 @@
+use crate::outputs::{Dac, Outputs};
+use crate::N_MODES;
+
+use etas_midi2cv_firmware::io::Pitches;
+use etas_midi2cv_firmware::modes::Mode;
+use etas_midi2cv_firmware::settings::Settings;
+use etas_midi2cv_firmware::storage::{Error, Flash, Reader, Storage, Writer, PAGE_SIZE};
+
+use core::ops::Range;
+use rtt_target::rprintln;
+use stm32f1xx_hal::flash::FlashWriter;
+
+/// Start of the `STORAGE` region in memory.x, relative to the start of the flash.
+const STORAGE_OFFSET: u32 = 0xf000;
+/// The log of the state, which is saved after every change.
+pub const STATE_PAGES: Range<usize> = 0..2;
+/// The log of the pitch tables, which are large but only change when tuning or calibrating.
+pub const TABLE_PAGES: Range<usize> = 2..4;
+
+pub struct InternalFlash<'a> {
+    writer: FlashWriter<'a>,
+}
+
+impl<'a> InternalFlash<'a> {
+    pub fn new(writer: FlashWriter<'a>) -> Self {
+        return Self { writer };
+    }
+}
+
+impl<'a> Flash for InternalFlash<'a> {
+    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), Error> {
+        let offset = STORAGE_OFFSET + offset as u32;
+        let bytes = self.writer.read(offset, data.len()).map_err(|_| Error::Flash)?;
+        data.copy_from_slice(bytes);
+        return Ok(());
+    }
+
+    fn erase_page(&mut self, page: usize) -> Result<(), Error> {
+        let offset = STORAGE_OFFSET + (page * PAGE_SIZE) as u32;
+        return self.writer.page_erase(offset).map_err(|_| Error::Flash);
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        let offset = STORAGE_OFFSET + offset as u32;
+        return self.writer.write(offset, data).map_err(|_| Error::Flash);
+    }
+}
+
+/// Serializes everything that survives a power cycle, but the pitch tables.
+pub fn save_state(
+    writer: &mut Writer,
+    selected_mode: i8,
+    settings: &Settings,
+    modes: &[&mut dyn Mode<Outputs>],
+    dac: &Dac,
+    pitches: &Pitches,
+) {
+    writer.i8(selected_mode);
+    settings.save(writer);
+    save_parts(writer, modes, dac, pitches);
+}
+
+/// Restores what [`save_state`] wrote, returning the selected mode. Nothing changes if the
+/// record is invalid.
+pub fn load_state(
+    reader: &mut Reader,
+    settings: &mut Settings,
+    modes: &mut [&mut dyn Mode<Outputs>],
+    dac: &mut Dac,
+    pitches: &mut Pitches,
+) -> Option<i8> {
+    let selected_mode = reader.i8()?;
+    let loaded = Settings::load(reader)?;
+    // the rest loads in place, so keep what it was to roll back to
+    let mut previous = Writer::default();
+    save_parts(&mut previous, modes, dac, pitches);
+    if load_parts(reader, modes, dac, pitches).is_none() {
+        load_parts(&mut Reader::new(previous.as_bytes().ok()?), modes, dac, pitches);
+        return None;
+    }
+    *settings = loaded;
+    return Some(selected_mode.rem_euclid(N_MODES as i8));
+}
+
+/// The modes and the outputs, which load in place.
+fn save_parts(
+    writer: &mut Writer,
+    modes: &[&mut dyn Mode<Outputs>],
+    dac: &Dac,
+    pitches: &Pitches,
+) {
+    for mode in modes {
+        mode.save(writer);
+    }
+    dac.save(writer);
+    pitches.save(writer);
+}
+
+fn load_parts(
+    reader: &mut Reader,
+    modes: &mut [&mut dyn Mode<Outputs>],
+    dac: &mut Dac,
+    pitches: &mut Pitches,
+) -> Option<()> {
+    for mode in modes.iter_mut() {
+        mode.load(reader)?;
+    }
+    dac.load(reader)?;
+    pitches.load(reader)?;
+    return Some(());
+}
+
+/// Opens the log on `pages`, returning the data of its current record. A log that can't be
+/// read is left alone, rather than saving over records that may still be valid.
+pub fn open_storage<'a>(
+    flash: &mut InternalFlash,
+    pages: Range<usize>,
+    data: &'a mut [u8],
+) -> (Option<Storage>, Option<&'a [u8]>) {
+    return match Storage::open(flash, pages, data) {
+        Ok((storage, len)) => {
+            let data: &'a [u8] = data;
+            (Some(storage), len.map(|len| &data[..len]))
+        },
+        Err(error) => {
+            rprintln!("opening the storage failed: {:?}", error);
+            (None, None)
+        },
+    };
+}
+
+/// Appends the record of `writer` to the log, if it could be opened.
+pub fn save_record(flash: &mut InternalFlash, storage: &mut Option<Storage>, writer: &Writer) {
+    if let Some(storage) = storage {
+        if let Err(error) = writer.as_bytes().and_then(|data| storage.save(flash, data)) {
+            rprintln!("saving settings failed: {:?}", error);
+        }
+    }
+}
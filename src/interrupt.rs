@@ -2,6 +2,8 @@ use crate::button::{Button, Event};
 use crate::outputs::Dac;
 use crate::N_MODES;
 
+use etas_midi2cv_firmware::context::{Context, Menu};
+
 use stm32f1xx_hal::gpio::{gpiob, Input, PullUp};
 use stm32f1xx_hal::pac::{interrupt, Interrupt, TIM2};
 use stm32f1xx_hal::prelude::*;
@@ -16,6 +18,9 @@ pub static PERIPHERALS: Mutex<Cell<Option<Peripherals>>> = Mutex::new(Cell::new(
 pub static CONTEXT: Mutex<Cell<Context>> = Mutex::new(Cell::new(Context::new(Menu::Main)));
 
 const LONG_PRESS_DELAY_MS: u32 = 600;
+const CAL_FINE_STEP: i16 = 1;
+const CAL_COARSE_STEP: i16 = 16;
+const N_LEARN_SLOTS: i8 = 6;
 
 #[interrupt]
 fn TIM2() {
@@ -26,6 +31,13 @@ fn TIM2() {
 
     let button_event_a = periphs.button_a.poll();
     let button_event_b = periphs.button_b.poll();
+    // pressing one button while the other is held, the releases are ignored
+    let is_combo = (button_event_a == Event::Down && !periphs.button_b.read())
+        || (button_event_b == Event::Down && !periphs.button_a.read());
+    if is_combo {
+        periphs.button_a.ignore_next_press();
+        periphs.button_b.ignore_next_press();
+    }
     match context.menu {
         Menu::Main => {
             match button_event_a {
@@ -35,7 +47,10 @@ fn TIM2() {
             }
             match button_event_b {
                 Event::Up => context.mode += 1,
-                Event::UpLong => context.menu = Menu::MidiLearn,
+                Event::UpLong => {
+                    context.menu = Menu::MidiLearn;
+                    context.learn_slot = 0;
+                },
                 _ => (),
             }
             context.mode = context.mode.rem_euclid(N_MODES as i8);
@@ -51,17 +66,42 @@ fn TIM2() {
                 Event::UpLong => context.cal_channel += 1,
                 _ => (),
             }
+            if is_combo {
+                context.menu = Menu::CalibrationEdit;
+                context.cal_offset = 0;
+            }
             context.cal_level = context.cal_level.rem_euclid(Dac::CAL_LEVELS.len() as i8);
             context.cal_channel = context.cal_channel.rem_euclid(Dac::N_CHANNELS as i8);
         },
+        Menu::CalibrationEdit => {
+            match button_event_a {
+                Event::Up => context.cal_offset -= CAL_FINE_STEP,
+                Event::UpLong => context.cal_offset -= CAL_COARSE_STEP,
+                _ => (),
+            }
+            match button_event_b {
+                Event::Up => context.cal_offset += CAL_FINE_STEP,
+                Event::UpLong => context.cal_offset += CAL_COARSE_STEP,
+                _ => (),
+            }
+            if is_combo {
+                context.menu = Menu::Calibration;
+            }
+        },
         Menu::MidiLearn => {
             match button_event_a {
                 Event::UpLong => context.menu = Menu::Main,
                 _ => (),
             }
             match button_event_b {
+                Event::Up => context.learn_slot += 1,
                 _ => (),
             }
+            context.learn_slot = context.learn_slot.rem_euclid(N_LEARN_SLOTS);
+            context.learn_hold = match button_event_b {
+                Event::Down | Event::Pressed | Event::DownLong => true,
+                _ => false,
+            };
         },
         Menu::Settings => {
             match button_event_a {
@@ -125,27 +165,3 @@ impl Peripherals {
 
 type PinButtonA = gpiob::PB3<Input<PullUp>>;
 type PinButtonB = gpiob::PB4<Input<PullUp>>;
-
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-pub enum Menu {
-    Main,
-    Calibration,
-    MidiLearn,
-    Settings,
-    SettingEdit,
-}
-
-#[derive(Copy, Clone, Debug)]
-pub struct Context {
-    pub menu: Menu,
-    pub mode: i8,
-    pub setting: i8,
-    pub cal_level: i8,
-    pub cal_channel: i8,
-}
-
-impl Context {
-    pub const fn new(default_menu: Menu) -> Self {
-        return Self { menu: default_menu, mode: 0, setting: 0, cal_level: 1, cal_channel: 0 };
-    }
-}
@@ -0,0 +1,340 @@
+use crate::storage::{Reader, Writer};
+use crate::tuning::{Table, Tuning};
+
+use fixed::types::{I16F16, U16F16};
+
+pub const N_GATES: usize = 6;
+pub const N_CVS: usize = 4;
+
+pub const ROOT_NOTE: u8 = 24; // C1
+const HZ_PER_VOLT_NOTE: i32 = 33; // A1, 55Hz at 1V
+
+/// 2^(n/12) for every semitone of an octave.
+const SEMITONE_RATIOS: [U16F16; 12] = [
+    U16F16::from_bits(65536),
+    U16F16::from_bits(69433),
+    U16F16::from_bits(73562),
+    U16F16::from_bits(77936),
+    U16F16::from_bits(82570),
+    U16F16::from_bits(87480),
+    U16F16::from_bits(92682),
+    U16F16::from_bits(98193),
+    U16F16::from_bits(104032),
+    U16F16::from_bits(110218),
+    U16F16::from_bits(116772),
+    U16F16::from_bits(123715),
+];
+/// ln(2) / 12
+const LN_SEMITONE: U16F16 = U16F16::from_bits(3786);
+
+pub trait GateSink {
+    fn set_gate(&mut self, gate: Gate, value: bool);
+}
+
+pub trait CvSink {
+    fn set_cv_voltage(&mut self, channel: Cv, voltage: U16F16);
+    fn set_cv_pitch(&mut self, channel: Cv, note: I16F16);
+    fn set_cv_bend(&mut self, channel: Cv, semitones: I16F16);
+
+    fn set_cv_note(&mut self, channel: Cv, note: u8) {
+        self.set_cv_pitch(channel, I16F16::from_num(note));
+    }
+
+    fn set_cv7(&mut self, channel: Cv, value: u8) {
+        self.set_cv_voltage(channel, U16F16::from_num(value) / 127 * 8);
+    }
+}
+
+/// Everything a mode drives, implemented by the hardware outputs and the host mock.
+pub trait Sink: GateSink + CvSink {}
+
+impl<T: GateSink + CvSink> Sink for T {}
+
+/// Note and pitch bend of every CV output, turned into voltages by the tuning and its pitch
+/// scale.
+#[derive(Debug)]
+pub struct Pitches {
+    notes: [I16F16; N_CVS],
+    bends: [I16F16; N_CVS],
+    scales: [PitchScale; N_CVS],
+    correction: Correction,
+    tuning: Tuning,
+    table: Table,
+}
+
+impl Default for Pitches {
+    fn default() -> Self {
+        return Self {
+            notes: [I16F16::ZERO; N_CVS],
+            bends: [I16F16::ZERO; N_CVS],
+            scales: [PitchScale::default(); N_CVS],
+            correction: Correction::default(),
+            tuning: Tuning::default(),
+            table: Table::default(),
+        };
+    }
+}
+
+impl Pitches {
+    pub fn scale(&self, channel: Cv) -> PitchScale {
+        return self.scales[channel as usize];
+    }
+
+    pub fn set_scale(&mut self, channel: Cv, scale: PitchScale) {
+        self.scales[channel as usize] = scale;
+    }
+
+    pub fn set_tuning(&mut self, tuning: Tuning) {
+        self.tuning = tuning;
+    }
+
+    pub fn table(&self) -> &Table {
+        return &self.table;
+    }
+
+    pub fn table_mut(&mut self) -> &mut Table {
+        return &mut self.table;
+    }
+
+    pub fn correction(&self) -> &Correction {
+        return &self.correction;
+    }
+
+    pub fn correction_mut(&mut self) -> &mut Correction {
+        return &mut self.correction;
+    }
+
+    pub fn set_note(&mut self, channel: Cv, note: I16F16) -> U16F16 {
+        self.notes[channel as usize] = note;
+        return self.voltage(channel);
+    }
+
+    pub fn set_bend(&mut self, channel: Cv, semitones: I16F16) -> U16F16 {
+        self.bends[channel as usize] = semitones;
+        return self.voltage(channel);
+    }
+
+    pub fn save(&self, writer: &mut Writer) {
+        for scale in &self.scales {
+            scale.save(writer);
+        }
+    }
+
+    pub fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        let mut scales = self.scales;
+        for scale in &mut scales {
+            *scale = PitchScale::load(reader)?;
+        }
+        self.scales = scales;
+        return Some(());
+    }
+
+    /// Saves the pitch correction and the tuning table, which are too large to save with the
+    /// settings.
+    pub fn save_tables(&self, writer: &mut Writer) {
+        self.correction.save(writer);
+        self.table.save(writer);
+    }
+
+    pub fn load_tables(&mut self, reader: &mut Reader) -> Option<()> {
+        let (mut correction, mut table) = (self.correction, self.table);
+        correction.load(reader)?;
+        table.load(reader)?;
+        self.correction = correction;
+        self.table = table;
+        return Some(());
+    }
+
+    fn voltage(&self, channel: Cv) -> U16F16 {
+        let scale = self.scales[channel as usize];
+        let pitch = self.tuning.pitch(self.notes[channel as usize], &self.table);
+        let note = pitch + self.bends[channel as usize];
+        let semitones = (note - I16F16::from_num(scale.root)).max(I16F16::ZERO);
+        let corrected = semitones + self.correction.cents(channel, semitones) / 100;
+        return scale.voltage(corrected.max(I16F16::ZERO));
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PitchStandard {
+    /// 1V/oct, the Eurorack and Moog standard.
+    VoltPerOctave,
+    /// 1.2V/oct, as used by Buchla.
+    Buchla,
+    /// Exponential, as used by Korg and Yamaha, with A1 at 1V.
+    HzPerVolt,
+    /// A user-defined number of volts per octave.
+    Custom,
+}
+
+impl PitchStandard {
+    pub const ALL: [Self; 4] = [
+        Self::VoltPerOctave,
+        Self::Buchla,
+        Self::HzPerVolt,
+        Self::Custom,
+    ];
+}
+
+/// How the notes of a CV output are turned into voltages.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PitchScale {
+    pub standard: PitchStandard,
+    /// Note at 0V, and of the first semitone correction. Hz/V only uses the latter.
+    pub root: u8,
+    /// Octave size of [`PitchStandard::Custom`] in millivolts.
+    pub millivolts: u16,
+}
+
+impl Default for PitchScale {
+    fn default() -> Self {
+        let standard = PitchStandard::VoltPerOctave;
+        return Self { standard, root: ROOT_NOTE, millivolts: 1000 };
+    }
+}
+
+impl PitchScale {
+    pub fn save(&self, writer: &mut Writer) {
+        writer.u8(self.standard as u8);
+        writer.u8(self.root);
+        writer.u16(self.millivolts);
+    }
+
+    pub fn load(reader: &mut Reader) -> Option<Self> {
+        return Some(Self {
+            standard: reader.variant(&PitchStandard::ALL)?,
+            root: reader.u8()?,
+            millivolts: reader.u16()?,
+        });
+    }
+
+    /// The voltage of a pitch `semitones` above the root note.
+    pub fn voltage(&self, semitones: I16F16) -> U16F16 {
+        let linear = U16F16::from_num(semitones.max(I16F16::ZERO)) / 12;
+        return match self.standard {
+            PitchStandard::VoltPerOctave => linear,
+            PitchStandard::Buchla => linear * 6 / 5,
+            PitchStandard::HzPerVolt => {
+                let offset = self.root as i32 - HZ_PER_VOLT_NOTE;
+                semitone_ratio(semitones + I16F16::from_num(offset))
+            },
+            PitchStandard::Custom => linear * (U16F16::from_num(self.millivolts) / 1000),
+        };
+    }
+}
+
+/// 2^(semitones / 12), exact on semitones and within a tenth of a cent in between.
+fn semitone_ratio(semitones: I16F16) -> U16F16 {
+    let octaves = (semitones / 12).floor().to_num::<i32>();
+    let rest = U16F16::from_num(semitones - I16F16::from_num(octaves * 12));
+    // second order series of e^x within the semitone
+    let x = rest.frac() * LN_SEMITONE;
+    let ratio = SEMITONE_RATIOS[rest.to_num::<usize>()] * (U16F16::ONE + x + x * x / 2);
+    if octaves < 0 {
+        return ratio >> (-octaves).min(31) as u32;
+    }
+    return ratio.saturating_mul_int(1 << octaves.min(15));
+}
+
+/// Pitch corrections in cents on top of the DAC calibration, which only has a point per volt.
+#[derive(Clone, Copy, Debug)]
+pub struct Correction {
+    fine_tune: [i8; N_CVS],
+    semitones: [[i8; Correction::N_SEMITONES]; N_CVS],
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        return Self { fine_tune: [0; N_CVS], semitones: [[0; Self::N_SEMITONES]; N_CVS] };
+    }
+}
+
+impl Correction {
+    /// One correction per semitone of the 8V range, including both ends.
+    pub const N_SEMITONES: usize = 8 * 12 + 1;
+
+    pub fn fine_tune(&self, channel: Cv) -> i8 {
+        return self.fine_tune[channel as usize];
+    }
+
+    pub fn set_fine_tune(&mut self, channel: Cv, cents: i8) {
+        self.fine_tune[channel as usize] = cents;
+    }
+
+    /// The correction at `semitone` semitones above the root note.
+    pub fn semitone(&self, channel: Cv, semitone: usize) -> i8 {
+        return self.semitones[channel as usize][semitone];
+    }
+
+    pub fn set_semitone(&mut self, channel: Cv, semitone: usize, cents: i8) {
+        self.semitones[channel as usize][semitone] = cents;
+    }
+
+    pub fn save(&self, writer: &mut Writer) {
+        for channel in Cv::ALL {
+            writer.i8(self.fine_tune[channel as usize]);
+            for &cents in &self.semitones[channel as usize] {
+                writer.i8(cents);
+            }
+        }
+    }
+
+    pub fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        let mut correction = *self;
+        for channel in Cv::ALL {
+            correction.fine_tune[channel as usize] = reader.i8()?;
+            for cents in &mut correction.semitones[channel as usize] {
+                *cents = reader.i8()?;
+            }
+        }
+        *self = correction;
+        return Some(());
+    }
+
+    /// Interpolates between the semitones around the (possibly bent) pitch.
+    fn cents(&self, channel: Cv, semitones: I16F16) -> I16F16 {
+        let corrections = &self.semitones[channel as usize];
+        let index = semitones.to_num::<usize>().min(Self::N_SEMITONES - 2);
+        let fraction = (semitones - I16F16::from_num(index)).min(I16F16::ONE);
+        let x0 = I16F16::from_num(corrections[index]);
+        let x1 = I16F16::from_num(corrections[index + 1]);
+        return fraction.lerp(x0, x1) + I16F16::from_num(self.fine_tune[channel as usize]);
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Gate {
+    G1,
+    G2,
+    G3,
+    G4,
+    G5,
+    G6,
+}
+
+impl From<u8> for Gate {
+    fn from(n: u8) -> Self {
+        return [Self::G1, Self::G2, Self::G3, Self::G4, Self::G5, Self::G6][n as usize];
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Cv {
+    Cv1,
+    Cv2,
+    Cv3,
+    Cv4,
+}
+
+impl Cv {
+    pub const ALL: [Self; N_CVS] = [Self::Cv1, Self::Cv2, Self::Cv3, Self::Cv4];
+}
+
+impl From<u8> for Cv {
+    fn from(n: u8) -> Self {
+        return [Self::Cv1, Self::Cv2, Self::Cv3, Self::Cv4][n as usize];
+    }
+}
@@ -0,0 +1,34 @@
+//! Hardware independent parts of the firmware. Without the default `hardware` feature this
+//! builds as a host library, so it can be tested with
+//! `cargo test --no-default-features --features host --target x86_64-unknown-linux-gnu`.
+
+#![no_std]
+#![allow(clippy::needless_return)]
+
+#[cfg(feature = "host")]
+extern crate std;
+
+extern crate embedded_midi;
+extern crate fixed;
+extern crate fugit;
+
+#[cfg(feature = "hardware")]
+#[macro_use]
+extern crate rtt_target;
+
+#[cfg(not(feature = "hardware"))]
+macro_rules! rprintln {
+    ($($arg:tt)*) => {};
+}
+
+pub mod calibration;
+pub mod context;
+pub mod io;
+pub mod midi;
+#[cfg(feature = "host")]
+pub mod mock;
+pub mod modes;
+pub mod note_stack;
+pub mod settings;
+pub mod storage;
+pub mod tuning;
@@ -3,22 +3,35 @@
 
 use binary_display::{BinaryDisplay, Millihertz};
 use display::DisplayPins;
-use interrupt::{Context, Menu, CONTEXT, PERIPHERALS};
-use modes::*;
+use flash::{
+    load_state, open_storage, save_record, save_state, InternalFlash, STATE_PAGES, TABLE_PAGES,
+};
+use interrupt::{CONTEXT, PERIPHERALS};
 use outputs::{Dac, Outputs};
-use settings::Settings;
+
+use etas_midi2cv_firmware::calibration::{Command, Remote};
+use etas_midi2cv_firmware::context::{Context, Menu};
+use etas_midi2cv_firmware::io::{Correction, Cv, CvSink, PitchScale, PitchStandard, Pitches};
+use etas_midi2cv_firmware::modes::*;
+use etas_midi2cv_firmware::settings::Settings;
+use etas_midi2cv_firmware::storage::{self, Reader, Writer};
+use etas_midi2cv_firmware::tuning::{MtsReceiver, Selector, TableUpdate};
 
 use cortex_m_rt::entry;
 use dwt_systick_monotonic::DwtSystick;
-use embedded_midi::MidiIn;
+use embedded_midi::{MidiMessage, MidiOut, MidiParser};
 use fugit::MicrosDurationU32;
 use rtic_monotonic::Monotonic;
 use rtt_target::{rprintln, rtt_init_print};
+use stm32f1xx_hal::flash::{FlashSize, SectorSize};
 use stm32f1xx_hal::prelude::*;
 use stm32f1xx_hal::spi::{NoMiso, Spi};
 use stm32f1xx_hal::{pac, serial};
 
-const N_MODES: usize = 1;
+const N_MODES: usize = 7;
+const ACTIVE_SENSING_TIMEOUT_US: u32 = 300_000;
+// saving erases flash now and then, which stalls the cpu, so wait until the user is done
+const SAVE_DELAY_US: u32 = 2_000_000;
 
 #[entry]
 fn main() -> ! {
@@ -35,9 +48,17 @@ fn main() -> ! {
     let mut gpiob = pac.GPIOB.split();
     let mut afio = pac.AFIO.constrain();
 
-    let settings = Settings::default();
+    let mut settings = Settings::default();
 
-    let mut modes: [&mut dyn Mode; N_MODES] = [&mut Mono::default()];
+    let mut modes: [&mut dyn Mode<Outputs>; N_MODES] = [
+        &mut Mono::default(),
+        &mut Poly::default(),
+        &mut Duo::default(),
+        &mut Drum::default(),
+        &mut Multi::default(),
+        &mut Split::default(),
+        &mut Arp::default(),
+    ];
 
     let led_pins = (
         gpiob.pb5.into_alternate_push_pull(&mut gpiob.crl),
@@ -54,13 +75,6 @@ fn main() -> ! {
     let pb4 = pb4.into_pull_up_input(&mut gpiob.crl);
     let mut isr_peripherals = interrupt::Peripherals::new((pb3, pb4), pac.TIM2, &clocks);
     let menu = if isr_peripherals.do_calibrate() { Menu::Calibration } else { Menu::Main };
-    cortex_m::interrupt::free(|cs| PERIPHERALS.borrow(cs).set(Some(isr_peripherals)));
-    unsafe {
-        interrupt::Peripherals::enable_isr();
-    }
-
-    let context = Context::new(menu);
-    cortex_m::interrupt::free(|cs| CONTEXT.borrow(cs).set(context));
 
     let gate_pins = (
         gpiob.pb0.into_push_pull_output(&mut gpiob.crl),
@@ -82,8 +96,36 @@ fn main() -> ! {
         Dac::SPI_FREQ,
         clocks,
     );
-    let dac = Dac::new(cs1, cs2);
-    let mut outputs = Outputs::new(gate_pins, spi, dac);
+    let mut dac = Dac::new(cs1, cs2);
+    let mut pitches = Pitches::default();
+
+    let flash_writer = flash.writer(SectorSize::Sz1K, FlashSize::Sz64K);
+    let mut storage_flash = InternalFlash::new(flash_writer);
+    let mut data = [0; storage::MAX_DATA];
+    let mut context = Context::new(menu);
+    let (mut storage, record) = open_storage(&mut storage_flash, STATE_PAGES, &mut data);
+    if let Some(record) = record {
+        let mut reader = Reader::new(record);
+        match load_state(&mut reader, &mut settings, &mut modes, &mut dac, &mut pitches) {
+            Some(mode) => context.mode = mode,
+            None => rprintln!("stored settings are invalid"),
+        }
+    }
+    let (mut table_storage, record) = open_storage(&mut storage_flash, TABLE_PAGES, &mut data);
+    if let Some(record) = record {
+        if pitches.load_tables(&mut Reader::new(record)).is_none() {
+            rprintln!("stored pitch tables are invalid");
+        }
+    }
+    pitches.set_tuning(settings.tuning);
+
+    cortex_m::interrupt::free(|cs| CONTEXT.borrow(cs).set(context));
+    cortex_m::interrupt::free(|cs| PERIPHERALS.borrow(cs).set(Some(isr_peripherals)));
+    unsafe {
+        interrupt::Peripherals::enable_isr();
+    }
+
+    let mut outputs = Outputs::new(gate_pins, spi, dac, pitches);
 
     let sysclk = clocks.sysclk().raw();
     let mut timer = DwtSystick::<72_000_000>::new(&mut core.DCB, core.DWT, core.SYST, sysclk);
@@ -100,8 +142,22 @@ fn main() -> ! {
         serial::Config::default().baudrate(31250.bps()).parity_none(),
         clocks,
     );
-    let (_tx, rx) = usart.split();
-    let mut midi_in = MidiIn::new(rx);
+    let (tx, mut rx) = usart.split();
+    let mut midi_parser = MidiParser::new();
+    let mut midi_out = MidiOut::new(tx);
+    let mut remote = Remote::default();
+    let mut selector = Selector::default();
+    let mut parameters = Parameters::default();
+    let mut mts = MtsReceiver::default();
+
+    let mut clock = Clock::default();
+    clock.set_gates(modes[context.mode as usize].clock_gates(), &mut outputs);
+
+    let mut sensing_time: Option<u32> = None;
+    let mut midi_errors: u32 = 0;
+    let mut dropped_notes: u32 = 0;
+    let mut save_time: Option<u32> = None;
+    let mut cal_output: Option<(Cv, u16)> = None;
 
     let mut last_time = timer.now();
     let mut last_context = context;
@@ -111,20 +167,114 @@ fn main() -> ! {
         last_time = now;
 
         let context = cortex_m::interrupt::free(|cs| CONTEXT.borrow(cs).get());
+        if last_context.mode != context.mode {
+            // release the gates and triggers the previous mode left open
+            modes[last_context.mode as usize].reset(&mut outputs);
+        }
         let mode = &mut modes[context.mode as usize];
+        if last_context.mode != context.mode {
+            clock.set_gates(mode.clock_gates(), &mut outputs);
+        }
 
-        match midi_in.read() {
-            Ok(message) => {
-                rprintln!("message {:?}", message);
-                match context.menu {
-                    Menu::Calibration => (),
-                    Menu::MidiLearn => mode.handle_midi_learn(message, &mut outputs),
-                    _ => mode.handle_midi_event(message, &mut outputs, &settings),
+        let message = match rx.read() {
+            Ok(byte) => {
+                // the parser skips sysex, so tuning dumps are picked from the raw bytes
+                let table = outputs.pitches_mut().table_mut();
+                if mts.parse_byte(byte, table) == Some(TableUpdate::BulkDump) {
+                    save_time = Some(0);
                 }
+                midi_parser.parse_byte(byte)
+            },
+            Err(nb::Error::Other(error)) => {
+                midi_errors = midi_errors.wrapping_add(1);
+                rprintln!("midi error {:?} ({} total)", error, midi_errors);
+                None
             },
-            Err(_) => (),
+            Err(nb::Error::WouldBlock) => None,
+        };
+        if let Some(message) = message {
+            rprintln!("message {:?}", message);
+            if sensing_time.is_some() || matches!(message, MidiMessage::ActiveSensing) {
+                sensing_time = Some(0);
+            }
+            if !context.menu.is_calibration() {
+                clock.handle_midi_event(message, &mut outputs, &settings);
+            }
+            if let Some(command) = remote.handle_midi_event(message, context.menu) {
+                if calibrate(command, &context, &mut outputs, &remote, &mut midi_out) {
+                    // save right away
+                    save_time = Some(SAVE_DELAY_US);
+                }
+            }
+            match context.menu {
+                Menu::Calibration | Menu::CalibrationEdit => (),
+                Menu::MidiLearn => mode.handle_midi_learn(message, &mut outputs, &context),
+                _ => {
+                    if selector.handle_midi_event(message, &mut settings.tuning) {
+                        outputs.pitches_mut().set_tuning(settings.tuning);
+                        save_time = Some(0);
+                    }
+                    if parameters.handle_midi_event(
+                        message,
+                        &mut **mode,
+                        &mut outputs,
+                        &mut settings,
+                    ) {
+                        save_time = Some(0);
+                    }
+                    handle_channel_mode(message, &mut **mode, &mut outputs, &mut settings);
+                    mode.handle_midi_event(message, &mut outputs, &settings);
+                },
+            }
+        }
+        if let Some(time) = &mut sensing_time {
+            *time = time.saturating_add(delta_time.to_micros());
+            if *time > ACTIVE_SENSING_TIMEOUT_US {
+                rprintln!("active sensing timeout");
+                sensing_time = None;
+                mode.reset(&mut outputs);
+                clock.reset(&mut outputs);
+            }
+        }
+        if mode.dropped_notes() != dropped_notes {
+            dropped_notes = mode.dropped_notes();
+            rprintln!("note stack overflow ({} notes dropped)", dropped_notes);
         }
         mode.update(delta_time.convert(), &mut outputs);
+        clock.update(delta_time.convert(), &mut outputs);
+
+        let is_cal_confirmed =
+            last_context.menu == Menu::CalibrationEdit && context.menu == Menu::Calibration;
+        if context.menu.is_calibration() {
+            let channel = Cv::from(context.cal_channel as u8);
+            let level = context.cal_level as usize;
+            let value = outputs.dac().calibration(channel.into(), level);
+            let offset = context.cal_offset as i32;
+            let edited = (value as i32 + offset).clamp(0, Dac::MAX_VALUE as i32) as u16;
+            let value = match context.menu {
+                Menu::CalibrationEdit => edited,
+                _ if is_cal_confirmed => {
+                    // leaving the editor confirms the edited value
+                    outputs.dac_mut().calibrate(channel.into(), level, edited);
+                    edited
+                },
+                _ => value,
+            };
+            if cal_output != Some((channel, value)) {
+                outputs.set_cv_raw(channel, value);
+                cal_output = Some((channel, value));
+            }
+        }
+        else {
+            cal_output = None;
+        }
+
+        if last_context.mode != context.mode
+            || (last_context.menu != context.menu && context.menu == Menu::Main)
+            || is_cal_confirmed
+        {
+            save_time = Some(0);
+        }
 
         if last_context.menu != context.menu {
             match context.menu {
@@ -134,6 +284,9 @@ fn main() -> ! {
                 Menu::Calibration => {
                     display.enable_breathing(7.Hz(), 15000, 4000);
                 },
+                Menu::CalibrationEdit => {
+                    display.enable_breathing(3.Hz(), 15000, 4000);
+                },
                 Menu::MidiLearn => {
                     display.enable_breathing(2.Hz(), 10000, 4000);
                 },
@@ -147,11 +300,29 @@ fn main() -> ! {
         }
         last_context = context;
 
+        if let Some(time) = &mut save_time {
+            *time = time.saturating_add(delta_time.to_micros());
+            if *time > SAVE_DELAY_US {
+                save_time = None;
+                let mut writer = Writer::default();
+                let (dac, pitches) = (outputs.dac(), outputs.pitches());
+                save_state(&mut writer, context.mode, &settings, &modes, dac, pitches);
+                save_record(&mut storage_flash, &mut storage, &writer);
+                // unchanged tables aren't written again, so this hardly wears the flash
+                let mut writer = Writer::default();
+                pitches.save_tables(&mut writer);
+                save_record(&mut storage_flash, &mut table_storage, &writer);
+            }
+        }
+
         match context.menu {
-            Menu::Main | Menu::MidiLearn => {
+            Menu::Main => {
                 display.set(0);
             },
-            Menu::Calibration => {
+            Menu::MidiLearn => {
+                display.set(context.learn_slot as u8 + 1);
+            },
+            Menu::Calibration | Menu::CalibrationEdit => {
                 display.set(context.cal_level as u8);
             },
             Menu::Settings => {
@@ -165,11 +336,87 @@ fn main() -> ! {
     }
 }
 
+/// Applies a remote calibration command, returns whether the calibration should be saved.
+/// The remote only sends commands in the calibration menu, where the selected level is output.
+fn calibrate(
+    command: Command,
+    context: &Context,
+    outputs: &mut Outputs,
+    remote: &Remote,
+    midi_out: &mut MidiOut<serial::Tx<pac::USART1>>,
+) -> bool {
+    let channel = Cv::from(context.cal_channel as u8);
+    let level = context.cal_level as usize;
+    let value = outputs.dac().calibration(channel.into(), level);
+    match command {
+        Command::SelectChannel(channel) if channel < Dac::N_CHANNELS => {
+            update_context(|context| context.cal_channel = channel as i8);
+        },
+        Command::SelectLevel(level) if (level as usize) < Dac::CAL_LEVELS.len() => {
+            update_context(|context| context.cal_level = level as i8);
+        },
+        Command::Set(value) => outputs.dac_mut().calibrate(channel.into(), level, value),
+        Command::Offset(offset) => {
+            let value = (value as i32 + offset as i32).max(0) as u16;
+            outputs.dac_mut().calibrate(channel.into(), level, value);
+        },
+        Command::SendTable => {
+            for channel in 0..Dac::N_CHANNELS {
+                for level in 0..Dac::CAL_LEVELS.len() {
+                    let value = outputs.dac().calibration(Cv::from(channel).into(), level);
+                    for msg in remote.table_entry(channel, level as u8, value) {
+                        midi_out.write(&msg).ok();
+                    }
+                }
+            }
+        },
+        Command::Save => return true,
+        Command::FineTune(cents) => {
+            let cents = cents.clamp(i8::MIN as i16, i8::MAX as i16) as i8;
+            outputs.pitches_mut().correction_mut().set_fine_tune(channel, cents);
+        },
+        Command::Correct { channel, semitone, cents }
+            if channel < Dac::N_CHANNELS && (semitone as usize) < Correction::N_SEMITONES =>
+        {
+            let (channel, semitone) = (Cv::from(channel), semitone as usize);
+            let cents = cents.clamp(i8::MIN as i16, i8::MAX as i16) as i8;
+            outputs.pitches_mut().correction_mut().set_semitone(channel, semitone, cents);
+            let root = outputs.pitches().scale(channel).root;
+            outputs.set_cv_note(channel, root.saturating_add(semitone as u8));
+        },
+        Command::Standard(index) if (index as usize) < PitchStandard::ALL.len() => {
+            let standard = PitchStandard::ALL[index as usize];
+            let scale = PitchScale { standard, ..outputs.pitches().scale(channel) };
+            outputs.pitches_mut().set_scale(channel, scale);
+        },
+        Command::Root(root) => {
+            let scale = PitchScale { root, ..outputs.pitches().scale(channel) };
+            outputs.pitches_mut().set_scale(channel, scale);
+        },
+        Command::Millivolts(millivolts) => {
+            let scale = PitchScale { millivolts, ..outputs.pitches().scale(channel) };
+            outputs.pitches_mut().set_scale(channel, scale);
+        },
+        _ => (),
+    }
+    return false;
+}
+
+fn update_context<F: FnOnce(&mut Context)>(update: F) {
+    cortex_m::interrupt::free(|cs| {
+        let cell = CONTEXT.borrow(cs);
+        let mut context = cell.get();
+        update(&mut context);
+        cell.set(context);
+    });
+}
+
 extern crate cortex_m;
 extern crate cortex_m_rt;
 extern crate rtt_target;
 
 extern crate embedded_hal;
+extern crate nb;
 
 extern crate stm32f1xx_hal;
 
@@ -186,10 +433,11 @@ extern crate rtic_monotonic;
 
 extern crate mcp49xx;
 
+extern crate etas_midi2cv_firmware;
+
 mod binary_display;
 mod button;
 mod display;
+mod flash;
 mod interrupt;
-mod modes;
 mod outputs;
-mod settings;
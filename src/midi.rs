@@ -0,0 +1,68 @@
+//! The parameter number controllers, which select what the data entry controllers set.
+//!
+//! NRPNs and RPNs are selected with an MSB and an LSB controller each. Selecting one kind
+//! deselects the other, and `0x7f, 0x7f` is the null parameter that deselects both.
+
+pub const DATA_ENTRY_MSB_CC: u8 = 6;
+pub const DATA_ENTRY_LSB_CC: u8 = 38;
+pub const NRPN_LSB_CC: u8 = 98;
+pub const NRPN_MSB_CC: u8 = 99;
+pub const RPN_LSB_CC: u8 = 100;
+pub const RPN_MSB_CC: u8 = 101;
+
+const NULL: u16 = 0x3fff;
+
+/// Follows the NRPN and RPN selection of a MIDI stream.
+#[derive(Clone, Copy, Debug)]
+pub struct ParameterNumber {
+    nrpn: u16,
+    rpn: u16,
+}
+
+impl Default for ParameterNumber {
+    fn default() -> Self {
+        return Self { nrpn: NULL, rpn: NULL };
+    }
+}
+
+impl ParameterNumber {
+    /// Returns whether `cc` was one of the parameter number controllers.
+    pub fn handle_cc(&mut self, cc: u8, value: u8) -> bool {
+        match cc {
+            NRPN_MSB_CC | NRPN_LSB_CC => {
+                self.nrpn = set_byte(self.nrpn, cc == NRPN_MSB_CC, value);
+                self.rpn = NULL;
+            },
+            RPN_MSB_CC | RPN_LSB_CC => {
+                self.rpn = set_byte(self.rpn, cc == RPN_MSB_CC, value);
+                self.nrpn = NULL;
+            },
+            _ => return false,
+        }
+        return true;
+    }
+
+    /// The MSB and LSB of the selected NRPN.
+    pub fn nrpn(&self) -> Option<(u8, u8)> {
+        return split(self.nrpn);
+    }
+
+    /// The MSB and LSB of the selected RPN.
+    pub fn rpn(&self) -> Option<(u8, u8)> {
+        return split(self.rpn);
+    }
+}
+
+fn set_byte(number: u16, is_msb: bool, value: u8) -> u16 {
+    if is_msb {
+        return (number & 0x7f) | (value as u16) << 7;
+    }
+    return (number & !0x7f) | value as u16;
+}
+
+fn split(number: u16) -> Option<(u8, u8)> {
+    if number == NULL {
+        return None;
+    }
+    return Some(((number >> 7) as u8, (number & 0x7f) as u8));
+}
@@ -0,0 +1,109 @@
+use crate::io::{Cv, CvSink, Gate, GateSink, Pitches, N_CVS, N_GATES};
+use crate::storage::{Error, Flash, N_PAGES, PAGE_SIZE};
+
+use fixed::types::{I16F16, U16F16};
+use std::vec;
+use std::vec::Vec;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Event {
+    Gate(Gate, bool),
+    Cv(Cv, U16F16),
+}
+
+/// Outputs for host tests, recording every gate and voltage change.
+#[derive(Default, Debug)]
+pub struct MockOutputs {
+    pub events: Vec<Event>,
+    gates: [bool; N_GATES],
+    voltages: [U16F16; N_CVS],
+    pitches: Pitches,
+}
+
+impl MockOutputs {
+    pub fn gate(&self, gate: Gate) -> bool {
+        return self.gates[gate as usize];
+    }
+
+    pub fn voltage(&self, channel: Cv) -> U16F16 {
+        return self.voltages[channel as usize];
+    }
+
+    pub fn pitches(&self) -> &Pitches {
+        return &self.pitches;
+    }
+
+    pub fn pitches_mut(&mut self) -> &mut Pitches {
+        return &mut self.pitches;
+    }
+
+    /// Returns the events recorded since the last call.
+    pub fn take_events(&mut self) -> Vec<Event> {
+        return self.events.drain(..).collect();
+    }
+}
+
+impl GateSink for MockOutputs {
+    fn set_gate(&mut self, gate: Gate, value: bool) {
+        self.gates[gate as usize] = value;
+        self.events.push(Event::Gate(gate, value));
+    }
+}
+
+impl CvSink for MockOutputs {
+    fn set_cv_voltage(&mut self, channel: Cv, voltage: U16F16) {
+        self.voltages[channel as usize] = voltage;
+        self.events.push(Event::Cv(channel, voltage));
+    }
+
+    fn set_cv_pitch(&mut self, channel: Cv, note: I16F16) {
+        let voltage = self.pitches.set_note(channel, note);
+        self.set_cv_voltage(channel, voltage);
+    }
+
+    fn set_cv_bend(&mut self, channel: Cv, semitones: I16F16) {
+        let voltage = self.pitches.set_bend(channel, semitones);
+        self.set_cv_voltage(channel, voltage);
+    }
+}
+
+/// Storage pages for host tests. Like the real flash, a half-word can only be written once
+/// after its page was erased.
+#[derive(Debug)]
+pub struct MockFlash {
+    pub data: Vec<u8>,
+    pub erases: [u32; N_PAGES],
+}
+
+impl Default for MockFlash {
+    fn default() -> Self {
+        return Self { data: vec![0xff; N_PAGES * PAGE_SIZE], erases: [0; N_PAGES] };
+    }
+}
+
+impl Flash for MockFlash {
+    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), Error> {
+        let source = self.data.get(offset..offset + data.len()).ok_or(Error::Flash)?;
+        data.copy_from_slice(source);
+        return Ok(());
+    }
+
+    fn erase_page(&mut self, page: usize) -> Result<(), Error> {
+        let start = page * PAGE_SIZE;
+        self.data.get_mut(start..start + PAGE_SIZE).ok_or(Error::Flash)?.fill(0xff);
+        self.erases[page] += 1;
+        return Ok(());
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        if !offset.is_multiple_of(2) || !data.len().is_multiple_of(2) {
+            return Err(Error::Flash);
+        }
+        let target = self.data.get_mut(offset..offset + data.len()).ok_or(Error::Flash)?;
+        if target.iter().any(|&byte| byte != 0xff) {
+            return Err(Error::Flash);
+        }
+        target.copy_from_slice(data);
+        return Ok(());
+    }
+}
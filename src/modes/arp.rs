@@ -0,0 +1,331 @@
+use crate::context::Context;
+use crate::io::{Cv, Gate, Sink};
+use crate::note_stack::{self, NoteStack, MAX_NOTES};
+use crate::settings::{NotePriority, Retrigger, Settings};
+use crate::storage::{Reader, Writer};
+
+use super::{
+    receive_channel, receives, set_value, set_variant, ClockGates, Mode, PitchBend, Rng,
+    Trigger, SOSTENUTO_CC, SUSTAIN_CC,
+};
+
+use embedded_midi::MidiMessage as Midi;
+use fugit::*;
+
+const MEMORY_DEPTH: usize = 8;
+const CLOCK_PPQN: u32 = 24;
+const CLOCK_TIMEOUT_US: u32 = 250_000;
+const MAX_OCTAVES: u8 = 4;
+const MIN_TEMPO: u16 = 30;
+const MAX_TEMPO: u16 = 240;
+// in percent of a step, the gate has to close before the next one
+const MAX_GATE_LENGTH: u8 = 99;
+const STACK_CONFIG: note_stack::Config = note_stack::Config {
+    priority: NotePriority::Latest,
+    retrigger: Retrigger::Ignore,
+    legato: false,
+};
+
+#[derive(Debug)]
+pub struct Arp {
+    settings: ArpSettings,
+    stack: NoteStack,
+    gate: Trigger,
+    reset: Trigger,
+    rng: Rng,
+    bend: PitchBend,
+    trigger_length: MicrosDurationU32,
+    step: usize,
+    step_time: u32,
+    clock_ticks: u32,
+    clock_time: u32,
+    /// Measured between the last two clock ticks, if the clock is running.
+    clock_period: Option<u32>,
+}
+
+impl Default for Arp {
+    fn default() -> Self {
+        return Self {
+            settings: ArpSettings::default(),
+            stack: NoteStack::new(MEMORY_DEPTH),
+            gate: Trigger::new(Gate::G1),
+            reset: Trigger::new(Gate::G2),
+            rng: Rng::default(),
+            bend: PitchBend::default(),
+            trigger_length: MicrosDurationU32::millis(5),
+            step: 0,
+            step_time: 0,
+            clock_ticks: 0,
+            clock_time: CLOCK_TIMEOUT_US,
+            clock_period: None,
+        };
+    }
+}
+
+#[derive(Debug)]
+struct ArpSettings {
+    midi_channel: u8,
+    order: ArpOrder,
+    octaves: u8,
+    division: ArpDivision,
+    gate_length: u8,
+    tempo: u16,
+}
+
+impl Default for ArpSettings {
+    fn default() -> Self {
+        return Self {
+            midi_channel: 0,
+            order: ArpOrder::Up,
+            octaves: 1,
+            division: ArpDivision::D16,
+            gate_length: 50,
+            tempo: 120,
+        };
+    }
+}
+
+impl ArpSettings {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.midi_channel);
+        writer.u8(self.order as u8);
+        writer.u8(self.octaves);
+        writer.u8(self.division as u8);
+        writer.u8(self.gate_length);
+        writer.u16(self.tempo);
+    }
+
+    fn load(reader: &mut Reader) -> Option<Self> {
+        return Some(Self {
+            midi_channel: reader.u8()?,
+            order: reader.variant(&ArpOrder::ALL)?,
+            octaves: reader.u8().filter(|octaves| (1..=MAX_OCTAVES).contains(octaves))?,
+            division: reader.variant(&ArpDivision::ALL)?,
+            gate_length: reader.u8().filter(|length| (1..=MAX_GATE_LENGTH).contains(length))?,
+            tempo: reader.u16().filter(|tempo| (MIN_TEMPO..=MAX_TEMPO).contains(tempo))?,
+        });
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ArpOrder {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+impl ArpOrder {
+    const ALL: [Self; 5] = [Self::Up, Self::Down, Self::UpDown, Self::Random, Self::AsPlayed];
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ArpDivision {
+    D4,
+    D8,
+    D8T,
+    D16,
+    D16T,
+    D32,
+}
+
+impl ArpDivision {
+    const ALL: [Self; 6] = [Self::D4, Self::D8, Self::D8T, Self::D16, Self::D16T, Self::D32];
+
+    fn clock_ticks(self) -> u32 {
+        return match self {
+            Self::D4 => 24,
+            Self::D8 => 12,
+            Self::D8T => 8,
+            Self::D16 => 6,
+            Self::D16T => 4,
+            Self::D32 => 3,
+        };
+    }
+}
+
+impl<O: Sink> Mode<O> for Arp {
+    fn handle_midi_event(&mut self, msg: Midi, outputs: &mut O, settings: &Settings) {
+        let channel = receive_channel(msg, self.settings.midi_channel, settings);
+        if self.bend.handle_midi_event(msg, channel) {
+            outputs.set_cv_bend(Cv::Cv1, self.bend.semitones());
+        }
+        self.trigger_length = settings.trigger_length.into();
+        let midi_channel = channel.into();
+        match msg {
+            Midi::NoteOn(ch, note, _) if ch == midi_channel => {
+                let change = self.stack.note_on(note.into(), &STACK_CONFIG);
+                if change.gate == Some(true) {
+                    self.step = 0;
+                    self.step_time = self.step_period();
+                }
+            },
+            Midi::NoteOff(ch, note, _) if ch == midi_channel => {
+                self.stack.note_off(note.into(), &STACK_CONFIG);
+            },
+            Midi::ControlChange(ch, cc, val) if ch == midi_channel => {
+                self.handle_cc(cc.into(), val.into());
+            },
+            Midi::TimingClock => {
+                // the first tick after a pause has no period yet
+                let is_running = self.clock_time < CLOCK_TIMEOUT_US;
+                self.clock_period = is_running.then_some(self.clock_time);
+                self.clock_time = 0;
+                self.clock_ticks += 1;
+                if self.clock_ticks >= self.settings.division.clock_ticks() {
+                    self.clock_ticks = 0;
+                    self.advance(outputs);
+                }
+            },
+            Midi::Start => {
+                self.step = 0;
+                self.clock_ticks = 0;
+            },
+            _ => (),
+        }
+    }
+
+    fn handle_midi_learn(&mut self, msg: Midi, _outputs: &mut O, _context: &Context) {
+        if let Midi::NoteOn(channel, _, _) = msg {
+            self.settings.midi_channel = channel.into();
+        }
+    }
+
+    fn update(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        let micros = delta_time.to_micros();
+        self.clock_time = self.clock_time.saturating_add(micros);
+        if self.clock_time >= CLOCK_TIMEOUT_US {
+            self.step_time = self.step_time.saturating_add(micros);
+            if self.step_time >= self.step_period() {
+                self.step_time = 0;
+                self.advance(outputs);
+            }
+        }
+        self.gate.update(delta_time, outputs);
+        self.reset.update(delta_time, outputs);
+    }
+
+    fn clock_gates(&self) -> ClockGates {
+        return ClockGates { clock: Some(Gate::G5), reset: Some(Gate::G6), run: Some(Gate::G3) };
+    }
+
+    fn reset(&mut self, outputs: &mut O) {
+        self.stack.reset();
+        self.step = 0;
+        self.gate.cancel(outputs);
+        self.reset.cancel(outputs);
+    }
+
+    fn save(&self, writer: &mut Writer) {
+        self.settings.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        self.settings = ArpSettings::load(reader)?;
+        return Some(());
+    }
+
+    fn dropped_notes(&self) -> u32 {
+        return self.stack.dropped();
+    }
+
+    fn basic_channel(&self) -> u8 {
+        return self.settings.midi_channel;
+    }
+
+    fn all_notes_off(&mut self, channel: u8, _outputs: &mut O, settings: &Settings) {
+        if receives(self.settings.midi_channel, channel, settings) {
+            self.stack.clear();
+            self.step = 0;
+        }
+    }
+
+    fn reset_controllers(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if receives(self.settings.midi_channel, channel, settings) {
+            self.bend.reset();
+            outputs.set_cv_bend(Cv::Cv1, self.bend.semitones());
+            self.handle_cc(SUSTAIN_CC, 0);
+            self.handle_cc(SOSTENUTO_CC, 0);
+        }
+    }
+
+    fn set_parameter(&mut self, parameter: u8, value: u8, _outputs: &mut O) -> bool {
+        let settings = &mut self.settings;
+        return match parameter {
+            0 => set_variant(&mut settings.order, &ArpOrder::ALL, value),
+            1 => set_value(&mut settings.octaves, value.clamp(1, MAX_OCTAVES)),
+            2 => set_variant(&mut settings.division, &ArpDivision::ALL, value),
+            // in percent of a step
+            3 => set_value(&mut settings.gate_length, value.clamp(1, MAX_GATE_LENGTH)),
+            // in steps of 2 BPM
+            4 => set_value(&mut settings.tempo, (value as u16 * 2).clamp(MIN_TEMPO, MAX_TEMPO)),
+            _ => false,
+        };
+    }
+}
+
+impl Arp {
+    /// The pedals hold the notes of the stack, the pattern plays on until they are released.
+    fn handle_cc(&mut self, cc: u8, value: u8) {
+        match cc {
+            SUSTAIN_CC => {
+                self.stack.set_sustain(value >= 64, &STACK_CONFIG);
+            },
+            SOSTENUTO_CC => {
+                self.stack.set_sostenuto(value >= 64, &STACK_CONFIG);
+            },
+            _ => (),
+        }
+    }
+
+    fn step_period(&self) -> u32 {
+        let ticks = self.settings.division.clock_ticks();
+        if let Some(period) = self.clock_period.filter(|_| self.clock_time < CLOCK_TIMEOUT_US) {
+            return period * ticks;
+        }
+        return 60_000_000 / self.settings.tempo as u32 * ticks / CLOCK_PPQN;
+    }
+
+    fn advance<O: Sink>(&mut self, outputs: &mut O) {
+        let n_notes = self.stack.len();
+        if n_notes == 0 {
+            self.step = 0;
+            return;
+        }
+
+        let mut notes = [0; MAX_NOTES];
+        notes[..n_notes].copy_from_slice(self.stack.notes());
+        match self.settings.order {
+            ArpOrder::Random | ArpOrder::AsPlayed => (),
+            _ => notes[..n_notes].sort_unstable(),
+        }
+
+        let n_steps = n_notes * self.settings.octaves.clamp(1, MAX_OCTAVES) as usize;
+        let pattern_length = match self.settings.order {
+            ArpOrder::UpDown if n_steps > 1 => 2 * n_steps - 2,
+            _ => n_steps,
+        };
+        if self.step >= pattern_length {
+            self.step = 0;
+        }
+
+        let index = match self.settings.order {
+            ArpOrder::Up | ArpOrder::AsPlayed => self.step,
+            ArpOrder::Down => n_steps - 1 - self.step,
+            ArpOrder::UpDown if self.step >= n_steps => pattern_length - self.step,
+            ArpOrder::UpDown => self.step,
+            ArpOrder::Random => self.rng.next() as usize % n_steps,
+        };
+        let octave = (index / n_notes) as u8;
+        let note = notes[index % n_notes].saturating_add(12 * octave).min(127);
+
+        if self.step == 0 {
+            self.reset.trigger(self.trigger_length, outputs);
+        }
+        let gate_length = self.step_period() / 100 * self.settings.gate_length as u32;
+        outputs.set_cv_note(Cv::Cv1, note);
+        self.gate.trigger(gate_length.micros(), outputs);
+        self.step += 1;
+    }
+}
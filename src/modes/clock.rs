@@ -0,0 +1,104 @@
+use crate::io::{Gate, GateSink};
+use crate::settings::Settings;
+
+use super::Trigger;
+
+use embedded_midi::MidiMessage as Midi;
+use fugit::*;
+
+const CLOCKS_PER_SIXTEENTH: u32 = 6;
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ClockGates {
+    pub clock: Option<Gate>,
+    pub reset: Option<Gate>,
+    pub run: Option<Gate>,
+}
+
+#[derive(Default, Debug)]
+pub struct Clock {
+    pulse: Option<Trigger>,
+    reset: Option<Trigger>,
+    run: Option<Gate>,
+    position: u32,
+    is_running: bool,
+}
+
+impl Clock {
+    pub fn set_gates<O: GateSink>(&mut self, gates: ClockGates, outputs: &mut O) {
+        // release the gates of the previous mode, the next one may use them otherwise
+        if let Some(pulse) = &mut self.pulse {
+            pulse.cancel(outputs);
+        }
+        if let Some(reset) = &mut self.reset {
+            reset.cancel(outputs);
+        }
+        if let Some(gate) = self.run.filter(|&gate| gates.run != Some(gate)) {
+            outputs.set_gate(gate, false);
+        }
+        self.pulse = gates.clock.map(Trigger::new);
+        self.reset = gates.reset.map(Trigger::new);
+        self.run = gates.run;
+        if let Some(gate) = self.run {
+            outputs.set_gate(gate, self.is_running);
+        }
+    }
+
+    pub fn handle_midi_event<O: GateSink>(
+        &mut self,
+        msg: Midi,
+        outputs: &mut O,
+        settings: &Settings,
+    ) {
+        let trigger_length = settings.trigger_length.into();
+        match msg {
+            Midi::TimingClock if self.is_running => {
+                if self.position.is_multiple_of(settings.clock_division.midi_clocks()) {
+                    if let Some(pulse) = &mut self.pulse {
+                        pulse.trigger(trigger_length, outputs);
+                    }
+                }
+                self.position = self.position.wrapping_add(1);
+            },
+            Midi::Start => {
+                self.position = 0;
+                self.set_running(true, outputs);
+                if let Some(reset) = &mut self.reset {
+                    reset.trigger(trigger_length, outputs);
+                }
+            },
+            Midi::Continue => self.set_running(true, outputs),
+            Midi::Stop => self.set_running(false, outputs),
+            Midi::SongPositionPointer(position) => {
+                self.position = u16::from(position) as u32 * CLOCKS_PER_SIXTEENTH;
+            },
+            _ => (),
+        }
+    }
+
+    pub fn update<O: GateSink>(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        if let Some(pulse) = &mut self.pulse {
+            pulse.update(delta_time, outputs);
+        }
+        if let Some(reset) = &mut self.reset {
+            reset.update(delta_time, outputs);
+        }
+    }
+
+    pub fn reset<O: GateSink>(&mut self, outputs: &mut O) {
+        if let Some(pulse) = &mut self.pulse {
+            pulse.cancel(outputs);
+        }
+        if let Some(reset) = &mut self.reset {
+            reset.cancel(outputs);
+        }
+        self.set_running(false, outputs);
+    }
+
+    fn set_running<O: GateSink>(&mut self, is_running: bool, outputs: &mut O) {
+        self.is_running = is_running;
+        if let Some(gate) = self.run {
+            outputs.set_gate(gate, is_running);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::context::Context;
+use crate::io::{Cv, Gate, Sink};
+use crate::settings::Settings;
+use crate::storage::{Reader, Writer};
+
+use super::{receive_channel, Mode, Trigger};
+
+use embedded_midi::MidiMessage as Midi;
+use fugit::*;
+
+const N_PADS: usize = 6;
+const N_ACCENTS: usize = 4;
+
+#[derive(Debug)]
+pub struct Drum {
+    settings: DrumSettings,
+    triggers: [Trigger; N_PADS],
+}
+
+impl Default for Drum {
+    fn default() -> Self {
+        return Self {
+            settings: DrumSettings::default(),
+            triggers: [0, 1, 2, 3, 4, 5].map(|i| Trigger::new(Gate::from(i))),
+        };
+    }
+}
+
+#[derive(Debug)]
+struct DrumSettings {
+    midi_channel: u8,
+    notes: [u8; N_PADS],
+}
+
+impl Default for DrumSettings {
+    fn default() -> Self {
+        // General MIDI kick, snare, closed hi-hat, open hi-hat, crash, ride
+        return Self { midi_channel: 9, notes: [36, 38, 42, 46, 49, 51] };
+    }
+}
+
+impl DrumSettings {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.midi_channel);
+        for note in self.notes {
+            writer.u8(note);
+        }
+    }
+
+    fn load(reader: &mut Reader) -> Option<Self> {
+        let midi_channel = reader.u8()?;
+        let mut notes = [0; N_PADS];
+        for note in &mut notes {
+            *note = reader.u8()?;
+        }
+        return Some(Self { midi_channel, notes });
+    }
+}
+
+impl<O: Sink> Mode<O> for Drum {
+    fn handle_midi_event(&mut self, msg: Midi, outputs: &mut O, settings: &Settings) {
+        let midi_channel = receive_channel(msg, self.settings.midi_channel, settings).into();
+        match msg {
+            Midi::NoteOn(ch, note, vel) if ch == midi_channel => {
+                let note: u8 = note.into();
+                for pad in 0..N_PADS {
+                    if self.settings.notes[pad] == note {
+                        self.triggers[pad].trigger(settings.trigger_length.into(), outputs);
+                        if pad < N_ACCENTS {
+                            outputs.set_cv7(Cv::from(pad as u8), vel.into());
+                        }
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn handle_midi_learn(&mut self, msg: Midi, outputs: &mut O, context: &Context) {
+        if let Midi::NoteOn(channel, note, _) = msg {
+            let pad = context.learn_slot as usize % N_PADS;
+            self.settings.midi_channel = channel.into();
+            self.settings.notes[pad] = note.into();
+            self.triggers[pad].trigger(100u32.millis(), outputs);
+        }
+    }
+
+    fn update(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        for trigger in &mut self.triggers {
+            trigger.update(delta_time, outputs);
+        }
+    }
+
+    fn reset(&mut self, outputs: &mut O) {
+        for trigger in &mut self.triggers {
+            trigger.cancel(outputs);
+        }
+    }
+
+    fn save(&self, writer: &mut Writer) {
+        self.settings.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        self.settings = DrumSettings::load(reader)?;
+        return Some(());
+    }
+
+    fn basic_channel(&self) -> u8 {
+        return self.settings.midi_channel;
+    }
+}
@@ -0,0 +1,242 @@
+use crate::context::Context;
+use crate::io::{Cv, Gate, Sink};
+use crate::settings::Settings;
+use crate::storage::{Reader, Writer};
+
+use super::{
+    receive_channel, receives, set_variant, ClockGates, Envelope, Mode, PitchBend, Trigger,
+    Voice, VoiceModulation, MOD_WHEEL_CC,
+};
+
+use embedded_midi::MidiMessage as Midi;
+use fugit::*;
+
+const MEMORY_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub struct Duo {
+    settings: DuoSettings,
+    voice_a: Voice,
+    voice_b: Voice,
+    trigger_a: Trigger,
+    trigger_b: Trigger,
+    bend: PitchBend,
+    envelopes: [Envelope; 2],
+}
+
+impl Default for Duo {
+    fn default() -> Self {
+        return Self {
+            settings: DuoSettings::default(),
+            voice_a: Voice::new(Gate::G1, Cv::Cv1, MEMORY_DEPTH),
+            voice_b: Voice::new(Gate::G3, Cv::Cv2, MEMORY_DEPTH),
+            trigger_a: Trigger::new(Gate::G2),
+            trigger_b: Trigger::new(Gate::G4),
+            bend: PitchBend::default(),
+            envelopes: Default::default(),
+        };
+    }
+}
+
+#[derive(Default, Debug)]
+struct DuoSettings {
+    midi_channel: u8,
+    modulation: VoiceModulation,
+}
+
+impl DuoSettings {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.midi_channel);
+        writer.u8(self.modulation as u8);
+    }
+
+    fn load(reader: &mut Reader) -> Option<Self> {
+        return Some(Self {
+            midi_channel: reader.u8()?,
+            modulation: reader.variant(&VoiceModulation::ALL)?,
+        });
+    }
+}
+
+impl<O: Sink> Mode<O> for Duo {
+    fn handle_midi_event(&mut self, msg: Midi, outputs: &mut O, settings: &Settings) {
+        let channel = receive_channel(msg, self.settings.midi_channel, settings);
+        if self.bend.handle_midi_event(msg, channel) {
+            for cv in [Cv::Cv1, Cv::Cv2] {
+                outputs.set_cv_bend(cv, self.bend.semitones());
+            }
+        }
+        let midi_channel = channel.into();
+        let trigger_length = settings.trigger_length.into();
+        let is_pressure = self.settings.modulation == VoiceModulation::Pressure;
+        match msg {
+            Midi::NoteOn(ch, note, vel) if ch == midi_channel => {
+                let note = note.into();
+                let is_free_b = !self.voice_b.contains(note);
+                let use_a = self.voice_a.contains(note)
+                    || (is_free_b && self.voice_a.len() <= self.voice_b.len());
+                let voice = if use_a {
+                    if !self.voice_a.note_on(note, outputs, settings) {
+                        return;
+                    }
+                    self.trigger_a.trigger(trigger_length, outputs);
+                    0
+                }
+                else {
+                    if !self.voice_b.note_on(note, outputs, settings) {
+                        return;
+                    }
+                    self.trigger_b.trigger(trigger_length, outputs);
+                    1
+                };
+                match self.settings.modulation {
+                    VoiceModulation::Velocity => outputs.set_cv7(Cv::Cv3, vel.into()),
+                    VoiceModulation::Envelope => {
+                        self.envelopes[voice].set_velocity(vel.into(), settings);
+                        self.envelopes[voice].retrigger();
+                    },
+                    VoiceModulation::Pressure => (),
+                }
+            },
+            Midi::NoteOff(ch, note, _) if ch == midi_channel => {
+                let note = note.into();
+                if self.voice_a.contains(note) {
+                    if self.voice_a.note_off(note, outputs, settings) {
+                        self.trigger_a.trigger(trigger_length, outputs);
+                        self.envelopes[0].retrigger();
+                    }
+                }
+                else if self.voice_b.note_off(note, outputs, settings) {
+                    self.trigger_b.trigger(trigger_length, outputs);
+                    self.envelopes[1].retrigger();
+                }
+            },
+            Midi::ChannelPressure(ch, val) if ch == midi_channel && is_pressure => {
+                outputs.set_cv7(Cv::Cv3, val.into());
+                outputs.set_cv7(Cv::Cv4, val.into());
+            },
+            Midi::KeyPressure(ch, note, val) if ch == midi_channel && is_pressure => {
+                let note = Some(note.into());
+                if self.voice_a.active_note() == note {
+                    outputs.set_cv7(Cv::Cv3, val.into());
+                }
+                if self.voice_b.active_note() == note {
+                    outputs.set_cv7(Cv::Cv4, val.into());
+                }
+            },
+            Midi::ControlChange(ch, cc, val) if ch == midi_channel => {
+                if self.voice_a.handle_cc(cc.into(), val.into(), outputs, settings) {
+                    self.trigger_a.trigger(trigger_length, outputs);
+                    self.envelopes[0].retrigger();
+                }
+                if self.voice_b.handle_cc(cc.into(), val.into(), outputs, settings) {
+                    self.trigger_b.trigger(trigger_length, outputs);
+                    self.envelopes[1].retrigger();
+                }
+                for envelope in &mut self.envelopes {
+                    envelope.handle_cc(cc.into(), val.into());
+                }
+                match cc.into() {
+                    MOD_WHEEL_CC if self.settings.modulation == VoiceModulation::Velocity => {
+                        outputs.set_cv7(Cv::Cv4, val.into());
+                    },
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn handle_midi_learn(&mut self, msg: Midi, _outputs: &mut O, _context: &Context) {
+        if let Midi::NoteOn(channel, _, _) = msg {
+            self.settings.midi_channel = channel.into();
+        }
+    }
+
+    fn update(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        self.voice_a.update(delta_time, outputs);
+        self.voice_b.update(delta_time, outputs);
+        self.trigger_a.update(delta_time, outputs);
+        self.trigger_b.update(delta_time, outputs);
+        if self.settings.modulation == VoiceModulation::Envelope {
+            let gates = [self.voice_a.is_gate_open(), self.voice_b.is_gate_open()];
+            for (i, envelope) in self.envelopes.iter_mut().enumerate() {
+                if let Some(voltage) = envelope.update(delta_time, gates[i]) {
+                    outputs.set_cv_voltage(Cv::from(2 + i as u8), voltage);
+                }
+            }
+        }
+    }
+
+    fn clock_gates(&self) -> ClockGates {
+        return ClockGates { clock: Some(Gate::G5), reset: Some(Gate::G6), run: None };
+    }
+
+    fn reset(&mut self, outputs: &mut O) {
+        self.voice_a.reset(outputs);
+        self.voice_b.reset(outputs);
+        self.trigger_a.cancel(outputs);
+        self.trigger_b.cancel(outputs);
+    }
+
+    fn save(&self, writer: &mut Writer) {
+        self.settings.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        self.settings = DuoSettings::load(reader)?;
+        return Some(());
+    }
+
+    fn dropped_notes(&self) -> u32 {
+        return self.voice_a.dropped().wrapping_add(self.voice_b.dropped());
+    }
+
+    fn basic_channel(&self) -> u8 {
+        return self.settings.midi_channel;
+    }
+
+    fn all_notes_off(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if receives(self.settings.midi_channel, channel, settings) {
+            self.voice_a.all_notes_off(outputs);
+            self.voice_b.all_notes_off(outputs);
+        }
+    }
+
+    fn reset_controllers(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if !receives(self.settings.midi_channel, channel, settings) {
+            return;
+        }
+        self.bend.reset();
+        for cv in [Cv::Cv1, Cv::Cv2] {
+            outputs.set_cv_bend(cv, self.bend.semitones());
+        }
+        let trigger_length = settings.trigger_length.into();
+        if self.voice_a.reset_controllers(outputs, settings) {
+            self.trigger_a.trigger(trigger_length, outputs);
+            self.envelopes[0].retrigger();
+        }
+        if self.voice_b.reset_controllers(outputs, settings) {
+            self.trigger_b.trigger(trigger_length, outputs);
+            self.envelopes[1].retrigger();
+        }
+        match self.settings.modulation {
+            VoiceModulation::Velocity => outputs.set_cv7(Cv::Cv4, 0),
+            VoiceModulation::Envelope => (),
+            VoiceModulation::Pressure => {
+                outputs.set_cv7(Cv::Cv3, 0);
+                outputs.set_cv7(Cv::Cv4, 0);
+            },
+        }
+    }
+
+    fn set_parameter(&mut self, parameter: u8, value: u8, outputs: &mut O) -> bool {
+        let modulation = &mut self.settings.modulation;
+        if parameter != 0 || !set_variant(modulation, &VoiceModulation::ALL, value) {
+            return false;
+        }
+        outputs.set_cv7(Cv::Cv3, 0);
+        outputs.set_cv7(Cv::Cv4, 0);
+        return true;
+    }
+}
@@ -0,0 +1,127 @@
+use crate::settings::Settings;
+
+use super::cc_to_micros;
+
+use fixed::types::U16F16;
+use fugit::*;
+
+const RELEASE_CC: u8 = 72;
+const ATTACK_CC: u8 = 73;
+const DECAY_CC: u8 = 75;
+const SUSTAIN_CC: u8 = 79;
+
+const LEVEL_MAX: u32 = 1 << 24;
+const MAX_VOLTAGE: u64 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Debug)]
+pub struct Envelope {
+    stage: Stage,
+    level: u32,
+    velocity: u32,
+    attack: u32,
+    decay: u32,
+    sustain: u32,
+    release: u32,
+    is_gate_open: bool,
+    output: u32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        return Self {
+            stage: Stage::Idle,
+            level: 0,
+            velocity: 127,
+            attack: 2_000,
+            decay: 200_000,
+            sustain: LEVEL_MAX,
+            release: 200_000,
+            is_gate_open: false,
+            output: 0,
+        };
+    }
+}
+
+impl Envelope {
+    pub fn handle_cc(&mut self, cc: u8, value: u8) {
+        match cc {
+            ATTACK_CC => self.attack = cc_to_micros(value),
+            DECAY_CC => self.decay = cc_to_micros(value),
+            SUSTAIN_CC => self.sustain = value as u32 * LEVEL_MAX / 127,
+            RELEASE_CC => self.release = cc_to_micros(value),
+            _ => (),
+        }
+    }
+
+    pub fn set_velocity(&mut self, velocity: u8, settings: &Settings) {
+        self.velocity = if settings.envelope_velocity { velocity as u32 } else { 127 };
+    }
+
+    pub fn retrigger(&mut self) {
+        if self.is_gate_open {
+            self.stage = Stage::Attack;
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        delta_time: MicrosDurationU32,
+        is_gate_open: bool,
+    ) -> Option<U16F16> {
+        if is_gate_open != self.is_gate_open {
+            self.is_gate_open = is_gate_open;
+            self.stage = if is_gate_open { Stage::Attack } else { Stage::Release };
+        }
+
+        let delta_time = delta_time.to_micros();
+        match self.stage {
+            Stage::Idle | Stage::Sustain => (),
+            Stage::Attack => {
+                self.level += Self::slope(LEVEL_MAX, delta_time, self.attack);
+                if self.level >= LEVEL_MAX {
+                    self.level = LEVEL_MAX;
+                    self.stage = Stage::Decay;
+                }
+            },
+            Stage::Decay => {
+                let step = Self::slope(LEVEL_MAX - self.sustain, delta_time, self.decay);
+                self.level = self.level.saturating_sub(step);
+                if self.level <= self.sustain {
+                    self.level = self.sustain;
+                    self.stage = Stage::Sustain;
+                }
+            },
+            Stage::Release => {
+                let step = Self::slope(LEVEL_MAX, delta_time, self.release);
+                self.level = self.level.saturating_sub(step);
+                if self.level == 0 {
+                    self.stage = Stage::Idle;
+                }
+            },
+        }
+
+        let voltage = self.level as u64 * MAX_VOLTAGE * self.velocity as u64 / 127;
+        let output = (voltage >> 8) as u32;
+        if output == self.output {
+            return None;
+        }
+        self.output = output;
+        return Some(U16F16::from_bits(output));
+    }
+
+    fn slope(range: u32, delta_time: u32, time: u32) -> u32 {
+        if time == 0 {
+            return range.max(1);
+        }
+        return ((range as u64 * delta_time as u64 / time as u64) as u32).max(1);
+    }
+}
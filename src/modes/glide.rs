@@ -0,0 +1,83 @@
+use crate::io::{Cv, CvSink};
+use crate::settings::{GlideMode, Settings};
+
+use super::cc_to_micros;
+
+use fixed::types::I16F16;
+use fugit::*;
+
+const PORTAMENTO_TIME_CC: u8 = 5;
+const PORTAMENTO_CC: u8 = 65;
+
+#[derive(Debug)]
+pub struct Glide {
+    cv: Cv,
+    start: I16F16,
+    target: I16F16,
+    elapsed: u32,
+    duration: u32,
+    time: u32,
+    is_enabled: bool,
+}
+
+impl Glide {
+    pub fn new(cv: Cv) -> Self {
+        return Self {
+            cv,
+            start: I16F16::ZERO,
+            target: I16F16::ZERO,
+            elapsed: 0,
+            duration: 0,
+            time: 0,
+            is_enabled: true,
+        };
+    }
+
+    pub fn handle_cc(&mut self, cc: u8, value: u8) {
+        match cc {
+            PORTAMENTO_TIME_CC => self.time = cc_to_micros(value),
+            PORTAMENTO_CC => self.is_enabled = value >= 64,
+            _ => (),
+        }
+    }
+
+    pub fn set_note<O: CvSink>(
+        &mut self,
+        note: u8,
+        is_legato: bool,
+        outputs: &mut O,
+        settings: &Settings,
+    ) {
+        let current = self.current();
+        self.target = I16F16::from_num(note);
+        self.elapsed = 0;
+        self.duration = match settings.glide_mode {
+            GlideMode::ConstantTime => self.time,
+            GlideMode::ConstantRate => {
+                let distance = (self.target - current).abs().to_bits() as u64;
+                (self.time as u64 * distance / (12 << 16)) as u32
+            },
+        };
+
+        if !self.is_enabled || (settings.legato && !is_legato) {
+            self.duration = 0;
+        }
+        self.start = current;
+        outputs.set_cv_pitch(self.cv, self.current());
+    }
+
+    pub fn update<O: CvSink>(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        if self.elapsed < self.duration {
+            self.elapsed = (self.elapsed + delta_time.to_micros()).min(self.duration);
+            outputs.set_cv_pitch(self.cv, self.current());
+        }
+    }
+
+    fn current(&self) -> I16F16 {
+        if self.elapsed >= self.duration {
+            return self.target;
+        }
+        let progress = (self.elapsed as u64 * (1 << 16) / self.duration as u64) as i32;
+        return self.start + (self.target - self.start) * I16F16::from_bits(progress);
+    }
+}
@@ -0,0 +1,409 @@
+mod arp;
+mod clock;
+mod drum;
+mod duo;
+mod envelope;
+mod glide;
+mod mono;
+mod multi;
+mod poly;
+mod split;
+
+pub use self::arp::Arp;
+pub use self::clock::{Clock, ClockGates};
+pub use self::drum::Drum;
+pub use self::duo::Duo;
+pub use self::mono::Mono;
+pub use self::multi::Multi;
+pub use self::poly::Poly;
+pub use self::split::Split;
+
+use self::envelope::Envelope;
+use self::glide::Glide;
+
+use crate::context::Context;
+use crate::io::{Cv, Gate, GateSink, Sink};
+use crate::midi::{ParameterNumber, DATA_ENTRY_MSB_CC};
+use crate::note_stack::{Change, NoteStack};
+use crate::settings::{Retrigger, Settings};
+use crate::storage::{Reader, Writer};
+
+use embedded_midi::MidiMessage as Midi;
+use fixed::types::I16F16;
+use fugit::*;
+
+const MOD_WHEEL_CC: u8 = 1;
+const SUSTAIN_CC: u8 = 64;
+const SOSTENUTO_CC: u8 = 66;
+const ALL_SOUND_OFF_CC: u8 = 120;
+const RESET_ALL_CONTROLLERS_CC: u8 = 121;
+const ALL_NOTES_OFF_CC: u8 = 123;
+const OMNI_OFF_CC: u8 = 124;
+const OMNI_ON_CC: u8 = 125;
+
+const RPN_PITCH_BEND_SENSITIVITY: (u8, u8) = (0, 0);
+
+pub const PARAMETER_NRPN_MSB: u8 = 0x60;
+pub const SETTING_NRPN_MSB: u8 = 0x61;
+
+fn cc_to_micros(value: u8) -> u32 {
+    return (value as u32).pow(2) * 250;
+}
+
+pub trait Mode<O: Sink> {
+    fn handle_midi_event(&mut self, msg: Midi, outputs: &mut O, settings: &Settings);
+    fn handle_midi_learn(&mut self, msg: Midi, outputs: &mut O, context: &Context);
+    #[allow(unused_variables)]
+    fn update(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {}
+    fn clock_gates(&self) -> ClockGates {
+        return ClockGates::default();
+    }
+    fn reset(&mut self, outputs: &mut O);
+    fn save(&self, writer: &mut Writer);
+    fn load(&mut self, reader: &mut Reader) -> Option<()>;
+    fn dropped_notes(&self) -> u32 {
+        return 0;
+    }
+    /// The channel receiving the channel mode messages.
+    fn basic_channel(&self) -> u8;
+    #[allow(unused_variables)]
+    fn all_notes_off(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {}
+    #[allow(unused_variables)]
+    fn reset_controllers(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {}
+    /// Sets one of the mode settings, returns whether it changed.
+    #[allow(unused_variables)]
+    fn set_parameter(&mut self, parameter: u8, value: u8, outputs: &mut O) -> bool {
+        return false;
+    }
+}
+
+/// Receives the settings of the active mode as NRPNs, `PARAMETER_NRPN_MSB` followed by the
+/// parameter, and the global settings as `SETTING_NRPN_MSB` followed by the setting.
+#[derive(Default, Debug)]
+pub struct Parameters {
+    number: ParameterNumber,
+}
+
+impl Parameters {
+    /// Returns whether the settings of `mode` or `settings` changed.
+    pub fn handle_midi_event<O: Sink>(
+        &mut self,
+        msg: Midi,
+        mode: &mut dyn Mode<O>,
+        outputs: &mut O,
+        settings: &mut Settings,
+    ) -> bool {
+        let (cc, value): (u8, u8) = match msg {
+            Midi::ControlChange(_, cc, value) => (cc.into(), value.into()),
+            _ => return false,
+        };
+        if cc != DATA_ENTRY_MSB_CC {
+            self.number.handle_cc(cc, value);
+            return false;
+        }
+        return match self.number.nrpn() {
+            Some((PARAMETER_NRPN_MSB, parameter)) => {
+                mode.set_parameter(parameter, value, outputs)
+            },
+            Some((SETTING_NRPN_MSB, setting)) => settings.set_parameter(setting, value),
+            _ => false,
+        };
+    }
+}
+
+/// Sets `setting` to `value`, returns whether it changed.
+pub(crate) fn set_value<T: PartialEq>(setting: &mut T, value: T) -> bool {
+    let is_changed = *setting != value;
+    *setting = value;
+    return is_changed;
+}
+
+/// Sets `setting` to the variant at index `value`, returns whether it changed.
+pub(crate) fn set_variant<T: Copy + PartialEq>(
+    setting: &mut T,
+    variants: &[T],
+    value: u8,
+) -> bool {
+    return match variants.get(value as usize) {
+        Some(&variant) => set_value(setting, variant),
+        None => false,
+    };
+}
+
+/// Handles the channel mode messages (CC 120-127) on behalf of every mode.
+pub fn handle_channel_mode<O: Sink>(
+    msg: Midi,
+    mode: &mut dyn Mode<O>,
+    outputs: &mut O,
+    settings: &mut Settings,
+) {
+    let (channel, cc) = match msg {
+        Midi::ControlChange(ch, cc, _) => (ch.into(), cc.into()),
+        _ => return,
+    };
+    match cc {
+        ALL_SOUND_OFF_CC | ALL_NOTES_OFF_CC => mode.all_notes_off(channel, outputs, settings),
+        RESET_ALL_CONTROLLERS_CC => mode.reset_controllers(channel, outputs, settings),
+        OMNI_OFF_CC | OMNI_ON_CC if channel == mode.basic_channel() => {
+            // omni mode changes imply all notes off
+            mode.all_notes_off(channel, outputs, settings);
+            settings.omni = cc == OMNI_ON_CC;
+        },
+        _ => (),
+    }
+}
+
+/// Returns the channel a mode listening on `midi_channel` should accept `msg` on.
+fn receive_channel(msg: Midi, midi_channel: u8, settings: &Settings) -> u8 {
+    if !settings.omni {
+        return midi_channel;
+    }
+    return match msg {
+        Midi::NoteOff(ch, _, _)
+        | Midi::NoteOn(ch, _, _)
+        | Midi::KeyPressure(ch, _, _)
+        | Midi::ControlChange(ch, _, _)
+        | Midi::ProgramChange(ch, _)
+        | Midi::ChannelPressure(ch, _)
+        | Midi::PitchBendChange(ch, _) => ch.into(),
+        _ => midi_channel,
+    };
+}
+
+fn receives(midi_channel: u8, channel: u8, settings: &Settings) -> bool {
+    return settings.omni || channel == midi_channel;
+}
+
+#[derive(Debug)]
+struct Voice {
+    gate: Gate,
+    stack: NoteStack,
+    glide: Glide,
+    reopen_time: u32,
+}
+
+impl Voice {
+    fn new(gate: Gate, cv: Cv, depth: usize) -> Self {
+        return Self {
+            gate,
+            stack: NoteStack::new(depth),
+            glide: Glide::new(cv),
+            reopen_time: 0,
+        };
+    }
+
+    fn len(&self) -> usize {
+        return self.stack.len();
+    }
+
+    fn contains(&self, note: u8) -> bool {
+        return self.stack.contains(note);
+    }
+
+    fn is_gate_open(&self) -> bool {
+        return !self.stack.is_empty() && self.reopen_time == 0;
+    }
+
+    fn active_note(&self) -> Option<u8> {
+        return self.stack.active_note();
+    }
+
+    fn dropped(&self) -> u32 {
+        return self.stack.dropped();
+    }
+
+    fn handle_cc<O: Sink>(
+        &mut self,
+        cc: u8,
+        value: u8,
+        outputs: &mut O,
+        settings: &Settings,
+    ) -> bool {
+        self.glide.handle_cc(cc, value);
+        let config = settings.note_stack();
+        let change = match cc {
+            SUSTAIN_CC => self.stack.set_sustain(value >= 64, &config),
+            SOSTENUTO_CC => self.stack.set_sostenuto(value >= 64, &config),
+            _ => return false,
+        };
+        return self.apply(change, outputs, settings);
+    }
+
+    fn reset_controllers<O: Sink>(&mut self, outputs: &mut O, settings: &Settings) -> bool {
+        let sustain = self.handle_cc(SUSTAIN_CC, 0, outputs, settings);
+        let sostenuto = self.handle_cc(SOSTENUTO_CC, 0, outputs, settings);
+        return sustain || sostenuto;
+    }
+
+    fn all_notes_off<O: Sink>(&mut self, outputs: &mut O) {
+        self.stack.clear();
+        self.reopen_time = 0;
+        outputs.set_gate(self.gate, false);
+    }
+
+    fn reset<O: Sink>(&mut self, outputs: &mut O) {
+        self.stack.reset();
+        self.reopen_time = 0;
+        outputs.set_gate(self.gate, false);
+    }
+
+    fn update<O: Sink>(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        self.glide.update(delta_time, outputs);
+        if self.reopen_time > 0 {
+            self.reopen_time = self.reopen_time.saturating_sub(delta_time.to_micros());
+            if self.reopen_time == 0 && !self.stack.is_empty() {
+                outputs.set_gate(self.gate, true);
+            }
+        }
+    }
+
+    fn note_on<O: Sink>(&mut self, note: u8, outputs: &mut O, settings: &Settings) -> bool {
+        let is_repeat = self.stack.contains(note);
+        let change = self.stack.note_on(note, &settings.note_stack());
+        if is_repeat && change.retrigger && settings.retrigger == Retrigger::Reopen {
+            let length: MicrosDurationU32 = settings.trigger_length.into();
+            self.reopen_time = length.to_micros();
+            outputs.set_gate(self.gate, false);
+        }
+        rprintln!("{:?}", self.stack.notes());
+        return self.apply(change, outputs, settings);
+    }
+
+    fn note_off<O: Sink>(&mut self, note: u8, outputs: &mut O, settings: &Settings) -> bool {
+        let change = self.stack.note_off(note, &settings.note_stack());
+        rprintln!("{:?}", self.stack.notes());
+        return self.apply(change, outputs, settings);
+    }
+
+    fn apply<O: Sink>(&mut self, change: Change, outputs: &mut O, settings: &Settings) -> bool {
+        if let Some(note) = change.note {
+            self.glide.set_note(note, change.gate.is_none(), outputs, settings);
+        }
+        if let Some(is_open) = change.gate {
+            self.reopen_time = 0;
+            outputs.set_gate(self.gate, is_open);
+        }
+        return change.retrigger;
+    }
+}
+
+#[derive(Debug)]
+struct Trigger {
+    gate: Gate,
+    time: u32,
+    length: u32,
+    is_active: bool,
+}
+
+impl Trigger {
+    fn new(gate: Gate) -> Self {
+        return Self { gate, time: 0, length: 0, is_active: false };
+    }
+
+    fn trigger<O: GateSink>(&mut self, length: MicrosDurationU32, outputs: &mut O) {
+        self.time = 0;
+        self.length = length.to_micros();
+        self.is_active = true;
+        outputs.set_gate(self.gate, true);
+        rprintln!("trigger on");
+    }
+
+    fn cancel<O: GateSink>(&mut self, outputs: &mut O) {
+        if self.is_active {
+            self.is_active = false;
+            outputs.set_gate(self.gate, false);
+        }
+    }
+
+    fn update<O: GateSink>(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        if self.is_active {
+            self.time += delta_time.to_micros();
+            if self.time > self.length {
+                self.is_active = false;
+                outputs.set_gate(self.gate, false);
+                rprintln!("trigger off");
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum VoiceModulation {
+    Velocity,
+    Envelope,
+    Pressure,
+}
+
+impl VoiceModulation {
+    const ALL: [Self; 3] = [Self::Velocity, Self::Envelope, Self::Pressure];
+}
+
+impl Default for VoiceModulation {
+    fn default() -> Self {
+        return Self::Velocity;
+    }
+}
+
+#[derive(Debug)]
+struct PitchBend {
+    value: i16,
+    range: u8,
+    number: ParameterNumber,
+}
+
+impl Default for PitchBend {
+    fn default() -> Self {
+        return Self { value: 0, range: 2, number: ParameterNumber::default() };
+    }
+}
+
+impl PitchBend {
+    fn handle_midi_event(&mut self, msg: Midi, midi_channel: u8) -> bool {
+        return match msg {
+            Midi::PitchBendChange(ch, value) if u8::from(ch) == midi_channel => {
+                self.value = value.into();
+                true
+            },
+            Midi::ControlChange(ch, cc, value) if u8::from(ch) == midi_channel => {
+                self.handle_cc(cc.into(), value.into())
+            },
+            _ => false,
+        };
+    }
+
+    fn handle_cc(&mut self, cc: u8, value: u8) -> bool {
+        if cc == DATA_ENTRY_MSB_CC && self.number.rpn() == Some(RPN_PITCH_BEND_SENSITIVITY) {
+            self.range = value.clamp(1, 24);
+            return true;
+        }
+        self.number.handle_cc(cc, value);
+        return false;
+    }
+
+    fn reset(&mut self) {
+        self.value = 0;
+        self.number = ParameterNumber::default();
+    }
+
+    fn semitones(&self) -> I16F16 {
+        return I16F16::from_num(self.value) / 8192 * I16F16::from_num(self.range);
+    }
+}
+
+#[derive(Debug)]
+struct Rng(u32);
+
+impl Default for Rng {
+    fn default() -> Self {
+        return Self(0x2545_f491);
+    }
+}
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        return self.0;
+    }
+}
@@ -0,0 +1,223 @@
+use crate::context::Context;
+use crate::io::{Cv, CvSink, Gate, Sink};
+use crate::settings::Settings;
+use crate::storage::{Reader, Writer};
+
+use super::{
+    receive_channel, receives, set_value, ClockGates, Envelope, Mode, PitchBend, Trigger, Voice,
+    MOD_WHEEL_CC,
+};
+
+use embedded_midi::MidiMessage as Midi;
+use fugit::*;
+
+const MEMORY_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub struct Mono {
+    settings: MonoSettings,
+    voice: Voice,
+    trigger: Trigger,
+    learn_visualizer: Trigger,
+    bend: PitchBend,
+    envelope: Envelope,
+}
+
+impl Default for Mono {
+    fn default() -> Self {
+        return Self {
+            settings: MonoSettings::default(),
+            voice: Voice::new(Gate::G1, Cv::Cv1, MEMORY_DEPTH),
+            trigger: Trigger::new(Gate::G2),
+            learn_visualizer: Trigger::new(Gate::G4),
+            bend: PitchBend::default(),
+            envelope: Envelope::default(),
+        };
+    }
+}
+
+#[derive(Default, Debug)]
+struct MonoSettings {
+    midi_channel: u8,
+    midi_cc: u8,
+    envelope_cv: Option<Cv>,
+    aftertouch_cv: Option<Cv>,
+}
+
+impl MonoSettings {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.midi_channel);
+        writer.u8(self.midi_cc);
+        writer.option(self.envelope_cv.map(|cv| cv as u8));
+        writer.option(self.aftertouch_cv.map(|cv| cv as u8));
+    }
+
+    fn load(reader: &mut Reader) -> Option<Self> {
+        return Some(Self {
+            midi_channel: reader.u8()?,
+            midi_cc: reader.u8()?,
+            envelope_cv: reader.option(&Cv::ALL)?,
+            aftertouch_cv: reader.option(&Cv::ALL)?,
+        });
+    }
+}
+
+impl<O: Sink> Mode<O> for Mono {
+    // the voice changes with every note, which doesn't belong in a match guard
+    #[allow(clippy::collapsible_match)]
+    fn handle_midi_event(&mut self, msg: Midi, outputs: &mut O, settings: &Settings) {
+        let channel = receive_channel(msg, self.settings.midi_channel, settings);
+        if self.bend.handle_midi_event(msg, channel) {
+            outputs.set_cv_bend(Cv::Cv1, self.bend.semitones());
+        }
+        let midi_channel = channel.into();
+        let midi_cc = self.settings.midi_cc;
+        match msg {
+            Midi::NoteOn(ch, note, vel) if ch == midi_channel => {
+                if self.voice.note_on(note.into(), outputs, settings) {
+                    self.trigger.trigger(settings.trigger_length.into(), outputs);
+                    self.envelope.set_velocity(vel.into(), settings);
+                    self.envelope.retrigger();
+                    self.set_cv7(Cv::Cv2, vel.into(), outputs);
+                }
+            },
+            Midi::NoteOff(ch, note, _) if ch == midi_channel => {
+                if self.voice.note_off(note.into(), outputs, settings) {
+                    self.trigger.trigger(settings.trigger_length.into(), outputs);
+                    self.envelope.retrigger();
+                }
+            },
+            Midi::ChannelPressure(ch, val) if ch == midi_channel => {
+                self.set_pressure(val.into(), outputs);
+            },
+            Midi::KeyPressure(ch, note, val) if ch == midi_channel => {
+                if self.voice.active_note() == Some(note.into()) {
+                    self.set_pressure(val.into(), outputs);
+                }
+            },
+            Midi::ControlChange(ch, cc, val) if ch == midi_channel => {
+                if self.voice.handle_cc(cc.into(), val.into(), outputs, settings) {
+                    self.trigger.trigger(settings.trigger_length.into(), outputs);
+                    self.envelope.retrigger();
+                }
+                self.envelope.handle_cc(cc.into(), val.into());
+                match cc.into() {
+                    MOD_WHEEL_CC => self.set_cv7(Cv::Cv3, val.into(), outputs),
+                    cc if cc == midi_cc => self.set_cv7(Cv::Cv4, val.into(), outputs),
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn handle_midi_learn(&mut self, msg: Midi, outputs: &mut O, _context: &Context) {
+        let midi_channel = self.settings.midi_channel.into();
+        match msg {
+            Midi::NoteOn(channel, _, _) => {
+                self.settings.midi_channel = channel.into();
+            },
+            Midi::ControlChange(channel, cc, _)
+                if channel == midi_channel && cc != MOD_WHEEL_CC.into() =>
+            {
+                self.settings.midi_cc = cc.into();
+                self.learn_visualizer.trigger(100u32.millis(), outputs);
+            },
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        self.voice.update(delta_time, outputs);
+        self.trigger.update(delta_time, outputs);
+        let voltage = self.envelope.update(delta_time, self.voice.is_gate_open());
+        if let (Some(voltage), Some(cv)) = (voltage, self.settings.envelope_cv) {
+            outputs.set_cv_voltage(cv, voltage);
+        }
+        self.learn_visualizer.update(delta_time, outputs);
+    }
+
+    fn clock_gates(&self) -> ClockGates {
+        return ClockGates { clock: Some(Gate::G5), reset: Some(Gate::G6), run: Some(Gate::G3) };
+    }
+
+    fn reset(&mut self, outputs: &mut O) {
+        self.voice.reset(outputs);
+        self.trigger.cancel(outputs);
+    }
+
+    fn save(&self, writer: &mut Writer) {
+        self.settings.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        self.settings = MonoSettings::load(reader)?;
+        return Some(());
+    }
+
+    fn dropped_notes(&self) -> u32 {
+        return self.voice.dropped();
+    }
+
+    fn basic_channel(&self) -> u8 {
+        return self.settings.midi_channel;
+    }
+
+    fn all_notes_off(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if receives(self.settings.midi_channel, channel, settings) {
+            self.voice.all_notes_off(outputs);
+        }
+    }
+
+    fn reset_controllers(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if !receives(self.settings.midi_channel, channel, settings) {
+            return;
+        }
+        self.bend.reset();
+        outputs.set_cv_bend(Cv::Cv1, self.bend.semitones());
+        if self.voice.reset_controllers(outputs, settings) {
+            self.trigger.trigger(settings.trigger_length.into(), outputs);
+            self.envelope.retrigger();
+        }
+        self.set_cv7(Cv::Cv3, 0, outputs);
+        self.set_cv7(Cv::Cv4, 0, outputs);
+        self.set_pressure(0, outputs);
+    }
+
+    fn set_parameter(&mut self, parameter: u8, value: u8, outputs: &mut O) -> bool {
+        // any CV but the pitch, or none
+        let cv = match value {
+            0 => None,
+            1..=3 => Some(Cv::from(value)),
+            _ => return false,
+        };
+        let setting = match parameter {
+            0 => &mut self.settings.envelope_cv,
+            1 => &mut self.settings.aftertouch_cv,
+            _ => return false,
+        };
+        let last = *setting;
+        if !set_value(setting, cv) {
+            return false;
+        }
+        // release the CV left behind
+        if let Some(last) = last {
+            self.set_cv7(last, 0, outputs);
+        }
+        return true;
+    }
+}
+
+impl Mono {
+    fn set_pressure<O: CvSink>(&self, value: u8, outputs: &mut O) {
+        if let Some(channel) = self.settings.aftertouch_cv {
+            self.set_cv7(channel, value, outputs);
+        }
+    }
+
+    fn set_cv7<O: CvSink>(&self, channel: Cv, value: u8, outputs: &mut O) {
+        if self.settings.envelope_cv != Some(channel) {
+            outputs.set_cv7(channel, value);
+        }
+    }
+}
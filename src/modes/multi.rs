@@ -0,0 +1,163 @@
+use crate::context::Context;
+use crate::io::{Cv, Gate, Sink};
+use crate::settings::{NotePriority, Settings};
+use crate::storage::{Reader, Writer};
+
+use super::{set_variant, ClockGates, Mode, PitchBend, Voice};
+
+use embedded_midi::MidiMessage as Midi;
+use fugit::*;
+
+const N_LANES: usize = 4;
+const MEMORY_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub struct Multi {
+    settings: MultiSettings,
+    voices: [Voice; N_LANES],
+    bends: [PitchBend; N_LANES],
+}
+
+impl Default for Multi {
+    fn default() -> Self {
+        return Self {
+            settings: MultiSettings::default(),
+            voices: [0, 1, 2, 3].map(|i| Voice::new(Gate::from(i), Cv::from(i), MEMORY_DEPTH)),
+            bends: Default::default(),
+        };
+    }
+}
+
+#[derive(Debug)]
+struct MultiSettings {
+    lanes: [LaneSettings; N_LANES],
+}
+
+impl Default for MultiSettings {
+    fn default() -> Self {
+        return Self {
+            lanes: [0, 1, 2, 3].map(|i| LaneSettings {
+                midi_channel: i,
+                note_priority: NotePriority::Latest,
+            }),
+        };
+    }
+}
+
+impl MultiSettings {
+    fn save(&self, writer: &mut Writer) {
+        for lane in &self.lanes {
+            writer.u8(lane.midi_channel);
+            writer.u8(lane.note_priority as u8);
+        }
+    }
+
+    fn load(reader: &mut Reader) -> Option<Self> {
+        let mut settings = Self::default();
+        for lane in &mut settings.lanes {
+            lane.midi_channel = reader.u8()?;
+            lane.note_priority = reader.variant(&NotePriority::ALL)?;
+        }
+        return Some(settings);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct LaneSettings {
+    midi_channel: u8,
+    note_priority: NotePriority,
+}
+
+impl<O: Sink> Mode<O> for Multi {
+    fn handle_midi_event(&mut self, msg: Midi, outputs: &mut O, settings: &Settings) {
+        for (i, lane) in self.settings.lanes.iter().enumerate() {
+            let voice = &mut self.voices[i];
+            let bend = &mut self.bends[i];
+            if bend.handle_midi_event(msg, lane.midi_channel) {
+                outputs.set_cv_bend(Cv::from(i as u8), bend.semitones());
+            }
+
+            let midi_channel = lane.midi_channel.into();
+            let settings = Settings { note_priority: lane.note_priority, ..*settings };
+            match msg {
+                Midi::NoteOn(ch, note, _) if ch == midi_channel => {
+                    voice.note_on(note.into(), outputs, &settings);
+                },
+                Midi::NoteOff(ch, note, _) if ch == midi_channel => {
+                    voice.note_off(note.into(), outputs, &settings);
+                },
+                Midi::ControlChange(ch, cc, val) if ch == midi_channel => {
+                    voice.handle_cc(cc.into(), val.into(), outputs, &settings);
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn handle_midi_learn(&mut self, msg: Midi, _outputs: &mut O, context: &Context) {
+        if let Midi::NoteOn(channel, _, _) = msg {
+            let lane = context.learn_slot as usize % N_LANES;
+            self.settings.lanes[lane].midi_channel = channel.into();
+        }
+    }
+
+    fn update(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        for voice in &mut self.voices {
+            voice.update(delta_time, outputs);
+        }
+    }
+
+    fn clock_gates(&self) -> ClockGates {
+        return ClockGates { clock: Some(Gate::G5), reset: Some(Gate::G6), run: None };
+    }
+
+    fn reset(&mut self, outputs: &mut O) {
+        for voice in &mut self.voices {
+            voice.reset(outputs);
+        }
+    }
+
+    fn save(&self, writer: &mut Writer) {
+        self.settings.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        self.settings = MultiSettings::load(reader)?;
+        return Some(());
+    }
+
+    fn dropped_notes(&self) -> u32 {
+        return self.voices.iter().fold(0, |sum, voice| sum.wrapping_add(voice.dropped()));
+    }
+
+    fn basic_channel(&self) -> u8 {
+        return self.settings.lanes[0].midi_channel;
+    }
+
+    fn all_notes_off(&mut self, channel: u8, outputs: &mut O, _settings: &Settings) {
+        for (i, lane) in self.settings.lanes.iter().enumerate() {
+            if lane.midi_channel == channel {
+                self.voices[i].all_notes_off(outputs);
+            }
+        }
+    }
+
+    fn reset_controllers(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        for (i, lane) in self.settings.lanes.iter().enumerate() {
+            if lane.midi_channel == channel {
+                let settings = Settings { note_priority: lane.note_priority, ..*settings };
+                self.voices[i].reset_controllers(outputs, &settings);
+                self.bends[i].reset();
+                outputs.set_cv_bend(Cv::from(i as u8), self.bends[i].semitones());
+            }
+        }
+    }
+
+    fn set_parameter(&mut self, parameter: u8, value: u8, _outputs: &mut O) -> bool {
+        // one note priority per lane
+        return match self.settings.lanes.get_mut(parameter as usize) {
+            Some(lane) => set_variant(&mut lane.note_priority, &NotePriority::ALL, value),
+            None => false,
+        };
+    }
+}
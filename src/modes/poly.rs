@@ -0,0 +1,267 @@
+use crate::context::Context;
+use crate::io::{Cv, Gate, GateSink, Sink};
+use crate::settings::{NotePriority, Settings, Voicing};
+use crate::storage::{Reader, Writer};
+
+use super::{
+    receive_channel, receives, ClockGates, Mode, PitchBend, Rng, SOSTENUTO_CC, SUSTAIN_CC,
+};
+
+use embedded_midi::MidiMessage as Midi;
+use fugit::*;
+
+const N_VOICES: usize = 4;
+
+#[derive(Default, Debug)]
+pub struct Poly {
+    settings: PolySettings,
+    voices: [PolyVoice; N_VOICES],
+    bend: PitchBend,
+    next_voice: usize,
+    age: u32,
+    rng: Rng,
+    is_sustained: bool,
+    is_sostenuto: bool,
+}
+
+#[derive(Default, Debug)]
+struct PolySettings {
+    midi_channel: u8,
+}
+
+impl PolySettings {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.midi_channel);
+    }
+
+    fn load(reader: &mut Reader) -> Option<Self> {
+        return Some(Self { midi_channel: reader.u8()? });
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+struct PolyVoice {
+    note: u8,
+    age: u32,
+    is_active: bool,
+    // released, but held open by the sustain or sostenuto pedal
+    is_pedal_held: bool,
+    // held when the sostenuto pedal went down
+    is_sostenuto: bool,
+    reopen_time: u32,
+}
+
+impl<O: Sink> Mode<O> for Poly {
+    fn handle_midi_event(&mut self, msg: Midi, outputs: &mut O, settings: &Settings) {
+        let channel = receive_channel(msg, self.settings.midi_channel, settings);
+        if self.bend.handle_midi_event(msg, channel) {
+            for i in 0..N_VOICES {
+                outputs.set_cv_bend(Cv::from(i as u8), self.bend.semitones());
+            }
+        }
+        let midi_channel = channel.into();
+        match msg {
+            Midi::NoteOn(ch, note, vel) if ch == midi_channel => {
+                self.note_on(note.into(), vel.into(), outputs, settings);
+            },
+            Midi::NoteOff(ch, note, _) if ch == midi_channel => {
+                self.note_off(note.into(), outputs);
+            },
+            Midi::ControlChange(ch, cc, val) if ch == midi_channel => {
+                self.handle_cc(cc.into(), val.into(), outputs);
+            },
+            _ => (),
+        }
+    }
+
+    fn handle_midi_learn(&mut self, msg: Midi, _outputs: &mut O, _context: &Context) {
+        if let Midi::NoteOn(channel, _, _) = msg {
+            self.settings.midi_channel = channel.into();
+        }
+    }
+
+    fn update(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            if voice.reopen_time > 0 {
+                voice.reopen_time = voice.reopen_time.saturating_sub(delta_time.to_micros());
+                if voice.reopen_time == 0 && voice.is_active {
+                    outputs.set_gate(Gate::from(i as u8), true);
+                }
+            }
+        }
+    }
+
+    fn clock_gates(&self) -> ClockGates {
+        return ClockGates { clock: Some(Gate::G5), reset: Some(Gate::G6), run: None };
+    }
+
+    fn reset(&mut self, outputs: &mut O) {
+        self.is_sustained = false;
+        self.is_sostenuto = false;
+        self.release_all(outputs);
+    }
+
+    fn save(&self, writer: &mut Writer) {
+        self.settings.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        self.settings = PolySettings::load(reader)?;
+        return Some(());
+    }
+
+    fn basic_channel(&self) -> u8 {
+        return self.settings.midi_channel;
+    }
+
+    fn all_notes_off(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if receives(self.settings.midi_channel, channel, settings) {
+            self.release_all(outputs);
+        }
+    }
+
+    fn reset_controllers(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if receives(self.settings.midi_channel, channel, settings) {
+            self.bend.reset();
+            for i in 0..N_VOICES {
+                outputs.set_cv_bend(Cv::from(i as u8), self.bend.semitones());
+            }
+            self.handle_cc(SUSTAIN_CC, 0, outputs);
+            self.handle_cc(SOSTENUTO_CC, 0, outputs);
+        }
+    }
+}
+
+impl Poly {
+    fn note_on<O: Sink>(
+        &mut self,
+        note: u8,
+        velocity: u8,
+        outputs: &mut O,
+        settings: &Settings,
+    ) {
+        let index = match self.voices.iter().position(|v| v.is_active && v.note == note) {
+            Some(index) => index,
+            None => match self.allocate(velocity, settings) {
+                Some(index) => index,
+                None => match self.steal(note, settings) {
+                    Some(index) => index,
+                    None => return,
+                },
+            },
+        };
+
+        let previous = self.voices[index];
+        self.age = self.age.wrapping_add(1);
+        self.voices[index] =
+            PolyVoice { note, age: self.age, is_active: true, ..PolyVoice::default() };
+        let gate = Gate::from(index as u8);
+        if previous.is_active && previous.note != note {
+            // close the gate for a trigger length, so the new note has a rising edge
+            let length: MicrosDurationU32 = settings.trigger_length.into();
+            self.voices[index].reopen_time = length.to_micros();
+            outputs.set_gate(gate, false);
+        }
+        outputs.set_cv_note(Cv::from(index as u8), note);
+        if self.voices[index].reopen_time == 0 {
+            outputs.set_gate(gate, true);
+        }
+    }
+
+    fn note_off<O: GateSink>(&mut self, note: u8, outputs: &mut O) {
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            if voice.is_active && voice.note == note && !voice.is_pedal_held {
+                if self.is_sustained || voice.is_sostenuto {
+                    voice.is_pedal_held = true;
+                }
+                else {
+                    voice.is_active = false;
+                    outputs.set_gate(Gate::from(i as u8), false);
+                }
+            }
+        }
+    }
+
+    fn handle_cc<O: GateSink>(&mut self, cc: u8, value: u8, outputs: &mut O) {
+        match cc {
+            SUSTAIN_CC => self.set_sustain(value >= 64, outputs),
+            SOSTENUTO_CC => self.set_sostenuto(value >= 64, outputs),
+            _ => (),
+        }
+    }
+
+    fn set_sustain<O: GateSink>(&mut self, is_down: bool, outputs: &mut O) {
+        self.is_sustained = is_down;
+        self.release_pedalled(outputs);
+    }
+
+    /// Sostenuto only holds the voices whose keys are down when the pedal is pressed.
+    fn set_sostenuto<O: GateSink>(&mut self, is_down: bool, outputs: &mut O) {
+        if is_down != self.is_sostenuto {
+            for voice in &mut self.voices {
+                voice.is_sostenuto = is_down && voice.is_active && !voice.is_pedal_held;
+            }
+        }
+        self.is_sostenuto = is_down;
+        self.release_pedalled(outputs);
+    }
+
+    fn release_pedalled<O: GateSink>(&mut self, outputs: &mut O) {
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            if voice.is_pedal_held && !self.is_sustained && !voice.is_sostenuto {
+                voice.is_active = false;
+                voice.is_pedal_held = false;
+                outputs.set_gate(Gate::from(i as u8), false);
+            }
+        }
+    }
+
+    fn release_all<O: GateSink>(&mut self, outputs: &mut O) {
+        for (i, voice) in self.voices.iter_mut().enumerate() {
+            voice.is_active = false;
+            voice.is_pedal_held = false;
+            voice.is_sostenuto = false;
+            outputs.set_gate(Gate::from(i as u8), false);
+        }
+    }
+
+    fn allocate(&mut self, velocity: u8, settings: &Settings) -> Option<usize> {
+        let voices = &self.voices;
+        let is_free = |i: &usize| !voices[*i].is_active;
+        return match settings.voicing {
+            Voicing::Poly => (0..N_VOICES).find(is_free),
+            Voicing::Cyclic => {
+                let index =
+                    (0..N_VOICES).map(|i| (self.next_voice + i) % N_VOICES).find(is_free);
+                if let Some(index) = index {
+                    self.next_voice = (index + 1) % N_VOICES;
+                }
+                index
+            },
+            Voicing::Random => {
+                let n_free = (0..N_VOICES).filter(is_free).count();
+                if n_free == 0 {
+                    return None;
+                }
+                let n = self.rng.next() as usize % n_free;
+                (0..N_VOICES).filter(is_free).nth(n)
+            },
+            Voicing::Velocity => Some(velocity as usize * N_VOICES / 128),
+        };
+    }
+
+    fn steal(&self, note: u8, settings: &Settings) -> Option<usize> {
+        let voices = self.voices.iter().enumerate();
+        return match settings.note_priority {
+            NotePriority::Latest => voices.max_by_key(|(_, v)| self.age.wrapping_sub(v.age)),
+            NotePriority::First => None,
+            NotePriority::Highest => {
+                voices.min_by_key(|(_, v)| v.note).filter(|(_, v)| note > v.note)
+            },
+            NotePriority::Lowest => {
+                voices.max_by_key(|(_, v)| v.note).filter(|(_, v)| note < v.note)
+            },
+        }
+        .map(|(i, _)| i);
+    }
+}
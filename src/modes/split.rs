@@ -0,0 +1,296 @@
+use crate::context::Context;
+use crate::io::{Cv, Gate, Sink};
+use crate::settings::{NotePriority, Settings};
+use crate::storage::{Reader, Writer};
+
+use super::{
+    receive_channel, receives, set_value, set_variant, ClockGates, Envelope, Mode, PitchBend,
+    Trigger, Voice, VoiceModulation,
+};
+
+use embedded_midi::MidiMessage as Midi;
+use fugit::*;
+
+const N_ZONES: usize = 2;
+const MAX_TRANSPOSE: i8 = 4;
+const MEMORY_DEPTH: usize = 8;
+
+#[derive(Debug)]
+pub struct Split {
+    settings: SplitSettings,
+    voices: [Voice; N_ZONES],
+    triggers: [Trigger; N_ZONES],
+    bend: PitchBend,
+    envelopes: [Envelope; N_ZONES],
+}
+
+impl Default for Split {
+    fn default() -> Self {
+        return Self {
+            settings: SplitSettings::default(),
+            voices: [
+                Voice::new(Gate::G1, Cv::Cv1, MEMORY_DEPTH),
+                Voice::new(Gate::G2, Cv::Cv2, MEMORY_DEPTH),
+            ],
+            triggers: [Trigger::new(Gate::G3), Trigger::new(Gate::G4)],
+            bend: PitchBend::default(),
+            envelopes: Default::default(),
+        };
+    }
+}
+
+#[derive(Debug)]
+struct SplitSettings {
+    midi_channel: u8,
+    split_note: u8,
+    zones: [ZoneSettings; N_ZONES],
+    modulation: VoiceModulation,
+}
+
+impl Default for SplitSettings {
+    fn default() -> Self {
+        let zone = ZoneSettings { transpose: 0, note_priority: NotePriority::Latest };
+        return Self {
+            midi_channel: 0,
+            split_note: 60,
+            zones: [zone; N_ZONES],
+            modulation: VoiceModulation::Velocity,
+        };
+    }
+}
+
+impl SplitSettings {
+    fn save(&self, writer: &mut Writer) {
+        writer.u8(self.midi_channel);
+        writer.u8(self.split_note);
+        for zone in &self.zones {
+            writer.i8(zone.transpose);
+            writer.u8(zone.note_priority as u8);
+        }
+        writer.u8(self.modulation as u8);
+    }
+
+    fn load(reader: &mut Reader) -> Option<Self> {
+        let midi_channel = reader.u8()?;
+        let split_note = reader.u8()?;
+        let mut zones = Self::default().zones;
+        for zone in &mut zones {
+            zone.transpose = reader.i8()?;
+            zone.note_priority = reader.variant(&NotePriority::ALL)?;
+        }
+        let modulation = reader.variant(&VoiceModulation::ALL)?;
+        return Some(Self { midi_channel, split_note, zones, modulation });
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ZoneSettings {
+    transpose: i8,
+    note_priority: NotePriority,
+}
+
+impl ZoneSettings {
+    fn transpose(&self, note: u8) -> u8 {
+        return (note as i16 + self.transpose as i16 * 12).clamp(0, 127) as u8;
+    }
+
+    fn apply(&self, settings: &Settings) -> Settings {
+        return Settings { note_priority: self.note_priority, ..*settings };
+    }
+}
+
+impl<O: Sink> Mode<O> for Split {
+    fn handle_midi_event(&mut self, msg: Midi, outputs: &mut O, settings: &Settings) {
+        let channel = receive_channel(msg, self.settings.midi_channel, settings);
+        if self.bend.handle_midi_event(msg, channel) {
+            for cv in [Cv::Cv1, Cv::Cv2] {
+                outputs.set_cv_bend(cv, self.bend.semitones());
+            }
+        }
+        let midi_channel = channel.into();
+        let trigger_length = settings.trigger_length.into();
+        let is_pressure = self.settings.modulation == VoiceModulation::Pressure;
+        match msg {
+            Midi::NoteOn(ch, note, vel) if ch == midi_channel => {
+                let note: u8 = note.into();
+                let zone = if note < self.settings.split_note { 0 } else { 1 };
+                let zone_settings = self.settings.zones[zone];
+                let note = zone_settings.transpose(note);
+                let settings = zone_settings.apply(settings);
+                if self.voices[zone].note_on(note, outputs, &settings) {
+                    self.triggers[zone].trigger(trigger_length, outputs);
+                    match self.settings.modulation {
+                        VoiceModulation::Velocity => {
+                            outputs.set_cv7(Cv::from(2 + zone as u8), vel.into());
+                        },
+                        VoiceModulation::Envelope => {
+                            self.envelopes[zone].set_velocity(vel.into(), &settings);
+                            self.envelopes[zone].retrigger();
+                        },
+                        VoiceModulation::Pressure => (),
+                    }
+                }
+            },
+            Midi::NoteOff(ch, note, _) if ch == midi_channel => {
+                let note: u8 = note.into();
+                for zone in 0..N_ZONES {
+                    let zone_settings = self.settings.zones[zone];
+                    let note = zone_settings.transpose(note);
+                    let settings = zone_settings.apply(settings);
+                    if self.voices[zone].note_off(note, outputs, &settings) {
+                        self.triggers[zone].trigger(trigger_length, outputs);
+                        self.envelopes[zone].retrigger();
+                    }
+                }
+            },
+            Midi::ChannelPressure(ch, val) if ch == midi_channel && is_pressure => {
+                for zone in 0..N_ZONES {
+                    outputs.set_cv7(Cv::from(2 + zone as u8), val.into());
+                }
+            },
+            Midi::KeyPressure(ch, note, val) if ch == midi_channel && is_pressure => {
+                let note: u8 = note.into();
+                for zone in 0..N_ZONES {
+                    let note = self.settings.zones[zone].transpose(note);
+                    if self.voices[zone].active_note() == Some(note) {
+                        outputs.set_cv7(Cv::from(2 + zone as u8), val.into());
+                    }
+                }
+            },
+            Midi::ControlChange(ch, cc, val) if ch == midi_channel => {
+                for zone in 0..N_ZONES {
+                    let settings = self.settings.zones[zone].apply(settings);
+                    if self.voices[zone].handle_cc(cc.into(), val.into(), outputs, &settings) {
+                        self.triggers[zone].trigger(trigger_length, outputs);
+                        self.envelopes[zone].retrigger();
+                    }
+                }
+                for envelope in &mut self.envelopes {
+                    envelope.handle_cc(cc.into(), val.into());
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn handle_midi_learn(&mut self, msg: Midi, outputs: &mut O, context: &Context) {
+        match msg {
+            Midi::NoteOn(_, note, _) if context.learn_hold => {
+                self.settings.split_note = note.into();
+                // the split note is the first of the upper zone
+                self.triggers[1].trigger(100u32.millis(), outputs);
+            },
+            Midi::NoteOn(channel, _, _) => {
+                self.settings.midi_channel = channel.into();
+            },
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, delta_time: MicrosDurationU32, outputs: &mut O) {
+        for voice in &mut self.voices {
+            voice.update(delta_time, outputs);
+        }
+        for trigger in &mut self.triggers {
+            trigger.update(delta_time, outputs);
+        }
+        if self.settings.modulation == VoiceModulation::Envelope {
+            for zone in 0..N_ZONES {
+                let is_gate_open = self.voices[zone].is_gate_open();
+                if let Some(voltage) = self.envelopes[zone].update(delta_time, is_gate_open) {
+                    outputs.set_cv_voltage(Cv::from(2 + zone as u8), voltage);
+                }
+            }
+        }
+    }
+
+    fn clock_gates(&self) -> ClockGates {
+        return ClockGates { clock: Some(Gate::G5), reset: Some(Gate::G6), run: None };
+    }
+
+    fn reset(&mut self, outputs: &mut O) {
+        for voice in &mut self.voices {
+            voice.reset(outputs);
+        }
+        for trigger in &mut self.triggers {
+            trigger.cancel(outputs);
+        }
+    }
+
+    fn save(&self, writer: &mut Writer) {
+        self.settings.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        self.settings = SplitSettings::load(reader)?;
+        return Some(());
+    }
+
+    fn dropped_notes(&self) -> u32 {
+        return self.voices.iter().fold(0, |sum, voice| sum.wrapping_add(voice.dropped()));
+    }
+
+    fn basic_channel(&self) -> u8 {
+        return self.settings.midi_channel;
+    }
+
+    fn all_notes_off(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if receives(self.settings.midi_channel, channel, settings) {
+            for voice in &mut self.voices {
+                voice.all_notes_off(outputs);
+            }
+        }
+    }
+
+    fn reset_controllers(&mut self, channel: u8, outputs: &mut O, settings: &Settings) {
+        if !receives(self.settings.midi_channel, channel, settings) {
+            return;
+        }
+        self.bend.reset();
+        for cv in [Cv::Cv1, Cv::Cv2] {
+            outputs.set_cv_bend(cv, self.bend.semitones());
+        }
+        let trigger_length = settings.trigger_length.into();
+        for zone in 0..N_ZONES {
+            let settings = self.settings.zones[zone].apply(settings);
+            if self.voices[zone].reset_controllers(outputs, &settings) {
+                self.triggers[zone].trigger(trigger_length, outputs);
+                self.envelopes[zone].retrigger();
+            }
+            if self.settings.modulation == VoiceModulation::Pressure {
+                outputs.set_cv7(Cv::from(2 + zone as u8), 0);
+            }
+        }
+    }
+
+    fn set_parameter(&mut self, parameter: u8, value: u8, outputs: &mut O) -> bool {
+        if parameter == 2 * N_ZONES as u8 {
+            let modulation = &mut self.settings.modulation;
+            if !set_variant(modulation, &VoiceModulation::ALL, value) {
+                return false;
+            }
+            for zone in 0..N_ZONES {
+                outputs.set_cv7(Cv::from(2 + zone as u8), 0);
+            }
+            return true;
+        }
+        // the transposition and note priority of each zone
+        let zone = parameter as usize / 2;
+        let zone_settings = match self.settings.zones.get_mut(zone) {
+            Some(zone_settings) => zone_settings,
+            None => return false,
+        };
+        if parameter % 2 == 1 {
+            let note_priority = &mut zone_settings.note_priority;
+            return set_variant(note_priority, &NotePriority::ALL, value);
+        }
+        // in octaves, centered on 64
+        let transpose = (value as i16 - 64).clamp(-MAX_TRANSPOSE as i16, MAX_TRANSPOSE as i16);
+        if !set_value(&mut zone_settings.transpose, transpose as i8) {
+            return false;
+        }
+        // held notes could not be released anymore
+        self.voices[zone].all_notes_off(outputs);
+        return true;
+    }
+}
@@ -0,0 +1,296 @@
+pub const MAX_NOTES: usize = 16;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NotePriority {
+    Latest,
+    First,
+    Highest,
+    Lowest,
+}
+
+impl NotePriority {
+    pub const ALL: [Self; 4] = [Self::Latest, Self::First, Self::Highest, Self::Lowest];
+}
+
+/// What to do when a note that is already held is played again.
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Retrigger {
+    Ignore,
+    Trigger,
+    Reopen,
+    Restack,
+}
+
+impl Retrigger {
+    pub const ALL: [Self; 4] = [Self::Ignore, Self::Trigger, Self::Reopen, Self::Restack];
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    pub priority: NotePriority,
+    pub retrigger: Retrigger,
+    pub legato: bool,
+}
+
+/// The effect of a note stack operation on the voice that plays it.
+///
+/// A pitch change without a gate change is legato, the gate was open before and stays open.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Change {
+    pub gate: Option<bool>,
+    pub note: Option<u8>,
+    pub retrigger: bool,
+}
+
+impl Change {
+    fn merge(self, other: Change) -> Change {
+        let gate = other.gate.or(self.gate);
+        let note = if gate == Some(false) { None } else { other.note.or(self.note) };
+        return Change { gate, note, retrigger: self.retrigger || other.retrigger };
+    }
+}
+
+/// Held notes of a monophonic voice in the order they were played, including the sustain and
+/// sostenuto pedal state.
+#[derive(Debug)]
+pub struct NoteStack {
+    notes: [u8; MAX_NOTES],
+    size: usize,
+    depth: usize,
+    active: usize,
+    keys: u128,
+    sostenuto_notes: u128,
+    is_sustained: bool,
+    is_sostenuto: bool,
+    dropped: u32,
+}
+
+impl NoteStack {
+    pub fn new(depth: usize) -> Self {
+        return Self {
+            notes: [0; MAX_NOTES],
+            size: 0,
+            depth: depth.clamp(1, MAX_NOTES),
+            active: 0,
+            keys: 0,
+            sostenuto_notes: 0,
+            is_sustained: false,
+            is_sostenuto: false,
+            dropped: 0,
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return self.size;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.size == 0;
+    }
+
+    pub fn notes(&self) -> &[u8] {
+        return &self.notes[..self.size];
+    }
+
+    pub fn contains(&self, note: u8) -> bool {
+        return self.notes().contains(&note);
+    }
+
+    pub fn active_note(&self) -> Option<u8> {
+        return self.notes().get(self.active).copied();
+    }
+
+    /// Number of notes dropped because the stack was full.
+    pub fn dropped(&self) -> u32 {
+        return self.dropped;
+    }
+
+    pub fn note_on(&mut self, note: u8, config: &Config) -> Change {
+        self.keys |= key(note);
+        if self.contains(note) {
+            return self.repeat(note, config);
+        }
+        let is_first = self.size == 0;
+        let mut is_changed = is_first;
+        if self.size == self.depth {
+            is_changed |= self.drop_note(config.priority);
+        }
+        self.notes[self.size] = note;
+        self.size += 1;
+
+        let index = self.size - 1;
+        let is_higher = self.notes[self.active] < note;
+        let active = match config.priority {
+            NotePriority::Latest => index,
+            NotePriority::First => 0,
+            NotePriority::Highest => if is_higher { index } else { self.active },
+            NotePriority::Lowest => if !is_higher { index } else { self.active },
+        };
+        is_changed |= active != self.active;
+        self.active = active;
+
+        return Change {
+            gate: if is_first { Some(true) } else { None },
+            note: if is_changed { Some(note) } else { None },
+            retrigger: is_first || (is_changed && !config.legato),
+        };
+    }
+
+    pub fn note_off(&mut self, note: u8, config: &Config) -> Change {
+        self.keys &= !key(note);
+        if self.is_pedal_held(note) {
+            return Change::default();
+        }
+        return self.release(note, config);
+    }
+
+    pub fn set_sustain(&mut self, is_down: bool, config: &Config) -> Change {
+        self.is_sustained = is_down;
+        return self.release_pedalled(config);
+    }
+
+    /// Sostenuto only holds the notes whose keys are down when the pedal is pressed.
+    pub fn set_sostenuto(&mut self, is_down: bool, config: &Config) -> Change {
+        if is_down && !self.is_sostenuto {
+            self.sostenuto_notes = self.keys;
+        }
+        else if !is_down {
+            self.sostenuto_notes = 0;
+        }
+        self.is_sostenuto = is_down;
+        return self.release_pedalled(config);
+    }
+
+    /// Forgets all notes, but keeps the pedal state.
+    pub fn clear(&mut self) {
+        self.size = 0;
+        self.active = 0;
+        self.keys = 0;
+        self.sostenuto_notes = 0;
+    }
+
+    pub fn reset(&mut self) {
+        self.is_sustained = false;
+        self.is_sostenuto = false;
+        self.clear();
+    }
+
+    fn repeat(&mut self, note: u8, config: &Config) -> Change {
+        return match config.retrigger {
+            Retrigger::Ignore => Change::default(),
+            Retrigger::Trigger | Retrigger::Reopen => {
+                Change { gate: None, note: None, retrigger: true }
+            },
+            Retrigger::Restack => {
+                if config.priority != NotePriority::Latest || self.active_note() == Some(note) {
+                    return Change::default();
+                }
+                if let Some(index) = self.position(note) {
+                    self.remove(index);
+                }
+                self.notes[self.size] = note;
+                self.size += 1;
+                self.active = self.size - 1;
+                Change { gate: None, note: Some(note), retrigger: !config.legato }
+            },
+        };
+    }
+
+    fn release(&mut self, note: u8, config: &Config) -> Change {
+        let index = match self.position(note) {
+            Some(index) => index,
+            None => return Change::default(),
+        };
+        self.remove(index);
+
+        if index < self.active {
+            self.active -= 1;
+            return Change::default();
+        }
+        if index > self.active {
+            return Change::default();
+        }
+        if self.size == 0 {
+            self.active = 0;
+            return Change { gate: Some(false), ..Change::default() };
+        }
+        self.active = self.select(config.priority);
+        let note = self.notes[self.active];
+        return Change { gate: None, note: Some(note), retrigger: !config.legato };
+    }
+
+    fn release_pedalled(&mut self, config: &Config) -> Change {
+        let mut change = Change::default();
+        let mut i = 0;
+        while i < self.size {
+            let note = self.notes[i];
+            if self.keys & key(note) == 0 && !self.is_pedal_held(note) {
+                change = change.merge(self.release(note, config));
+            }
+            else {
+                i += 1;
+            }
+        }
+        return change;
+    }
+
+    /// Makes room for a new note by dropping the held note with the lowest priority, returns
+    /// whether that was the active note.
+    fn drop_note(&mut self, priority: NotePriority) -> bool {
+        let last = self.size - 1;
+        let index = match priority {
+            NotePriority::Latest => 0,
+            NotePriority::First => last,
+            NotePriority::Highest => self.lowest(),
+            NotePriority::Lowest => self.highest(),
+        };
+        self.remove(index);
+        self.dropped = self.dropped.wrapping_add(1);
+        if index < self.active {
+            self.active -= 1;
+        }
+        else if index == self.active {
+            self.active = 0;
+            return true;
+        }
+        return false;
+    }
+
+    fn select(&self, priority: NotePriority) -> usize {
+        return match priority {
+            NotePriority::Latest => self.size - 1,
+            NotePriority::First => 0,
+            NotePriority::Highest => self.highest(),
+            NotePriority::Lowest => self.lowest(),
+        };
+    }
+
+    fn highest(&self) -> usize {
+        let notes = self.notes();
+        return (0..notes.len()).max_by_key(|&i| notes[i]).unwrap_or(0);
+    }
+
+    fn lowest(&self) -> usize {
+        let notes = self.notes();
+        return (0..notes.len()).min_by_key(|&i| notes[i]).unwrap_or(0);
+    }
+
+    fn position(&self, note: u8) -> Option<usize> {
+        return self.notes().iter().position(|&n| n == note);
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.notes.copy_within(index + 1..self.size, index);
+        self.size -= 1;
+    }
+
+    fn is_pedal_held(&self, note: u8) -> bool {
+        return self.is_sustained || self.sostenuto_notes & key(note) != 0;
+    }
+}
+
+fn key(note: u8) -> u128 {
+    return 1 << (note & 0x7f);
+}
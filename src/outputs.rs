@@ -1,5 +1,8 @@
+use etas_midi2cv_firmware::io::{Cv, CvSink, Gate, GateSink, Pitches, N_CVS, N_GATES};
+use etas_midi2cv_firmware::storage::{Reader, Writer};
+
 use embedded_hal::spi::{Mode, MODE_0};
-use fixed::types::U16F16;
+use fixed::types::{I16F16, U16F16};
 use fugit::*;
 use mcp49xx::marker::{DualChannel, Resolution12Bit, Unbuffered};
 use mcp49xx::{Channel, Command, Mcp49xx};
@@ -8,13 +11,14 @@ use stm32f1xx_hal::pac::SPI1;
 use stm32f1xx_hal::spi::{NoMiso, Spi, Spi1NoRemap};
 
 pub struct Outputs {
-    gate_pins: [ErasedPin<Output<PushPull>>; 6],
+    gate_pins: [ErasedPin<Output<PushPull>>; N_GATES],
     spi: OutputsSpi,
     dac: Dac,
+    pitches: Pitches,
 }
 
 impl Outputs {
-    pub fn new(gates: PinsGate, spi: OutputsSpi, dac: Dac) -> Self {
+    pub fn new(gates: PinsGate, spi: OutputsSpi, dac: Dac, pitches: Pitches) -> Self {
         let mut gate_pins = [
             gates.0.erase(),
             gates.1.erase(),
@@ -28,65 +32,61 @@ impl Outputs {
             gate_pin.set_high();
         }
 
-        return Self { gate_pins, spi, dac };
+        return Self { gate_pins, spi, dac, pitches };
     }
 
-    const ROOT_NOTE: u8 = 24; // C1
-    pub fn set_cv_note(&mut self, channel: Cv, note: u8) {
-        let note_voltage = U16F16::from_num(note.saturating_sub(Self::ROOT_NOTE)) / 12;
-        self.dac.set_voltage(note_voltage, channel.into(), &mut self.spi);
+    pub fn dac(&self) -> &Dac {
+        return &self.dac;
     }
 
-    pub fn set_cv7(&mut self, channel: Cv, value: u8) {
-        let voltage = U16F16::from_num(value) / 127 * 8;
-        self.dac.set_voltage(voltage, channel.into(), &mut self.spi);
+    pub fn dac_mut(&mut self) -> &mut Dac {
+        return &mut self.dac;
     }
 
-    pub fn set_gate(&mut self, gate: Gate, value: bool) {
-        let state = if value { PinState::Low } else { PinState::High };
-        self.gate_pins[gate as usize].set_state(state);
+    pub fn pitches(&self) -> &Pitches {
+        return &self.pitches;
     }
-}
 
-#[repr(u8)]
-#[derive(Clone, Copy, Debug)]
-pub enum Gate {
-    G1,
-    G2,
-    G3,
-    G4,
-    G5,
-    G6,
-}
+    pub fn pitches_mut(&mut self) -> &mut Pitches {
+        return &mut self.pitches;
+    }
 
-impl From<u8> for Gate {
-    fn from(n: u8) -> Self {
-        return [Self::G1, Self::G2, Self::G3, Self::G4, Self::G5, Self::G6][n as usize];
+    /// Outputs a raw DAC code, bypassing the calibration.
+    pub fn set_cv_raw(&mut self, channel: Cv, value: u16) {
+        self.dac.set(value, channel.into(), &mut self.spi);
     }
 }
 
-#[repr(u8)]
-#[derive(Clone, Copy, Debug)]
-pub enum Cv {
-    Cv1,
-    Cv2,
-    Cv3,
-    Cv4,
+impl GateSink for Outputs {
+    fn set_gate(&mut self, gate: Gate, value: bool) {
+        let state = if value { PinState::Low } else { PinState::High };
+        self.gate_pins[gate as usize].set_state(state);
+    }
 }
 
-impl From<u8> for Cv {
-    fn from(n: u8) -> Self {
-        return [Self::Cv1, Self::Cv2, Self::Cv3, Self::Cv4][n as usize];
+impl CvSink for Outputs {
+    fn set_cv_voltage(&mut self, channel: Cv, voltage: U16F16) {
+        self.dac.set_voltage(voltage, channel.into(), &mut self.spi);
+    }
+
+    fn set_cv_pitch(&mut self, channel: Cv, note: I16F16) {
+        let voltage = self.pitches.set_note(channel, note);
+        self.set_cv_voltage(channel, voltage);
+    }
+
+    fn set_cv_bend(&mut self, channel: Cv, semitones: I16F16) {
+        let voltage = self.pitches.set_bend(channel, semitones);
+        self.set_cv_voltage(channel, voltage);
     }
 }
 
-impl Into<DacChannel> for Cv {
-    fn into(self) -> DacChannel {
-        return match self {
-            Self::Cv1 => DacChannel::C0,
-            Self::Cv2 => DacChannel::C1,
-            Self::Cv3 => DacChannel::C2,
-            Self::Cv4 => DacChannel::C3,
+impl From<Cv> for DacChannel {
+    fn from(cv: Cv) -> Self {
+        return match cv {
+            Cv::Cv1 => Self::C0,
+            Cv::Cv2 => Self::C1,
+            Cv::Cv3 => Self::C2,
+            Cv::Cv4 => Self::C3,
         };
     }
 }
@@ -108,11 +108,12 @@ pub struct Dac {
 }
 
 impl Dac {
-    pub const N_CHANNELS: u8 = 4;
+    pub const N_CHANNELS: u8 = N_CVS as u8;
     pub const CAL_LEVELS: [u8; 9] = [0, 1, 2, 3, 4, 5, 6, 7, 8];
     pub const DEFAULT_CAL: [u16; 9] = [0, 500, 1000, 1500, 2000, 2500, 3000, 3500, 4000];
     pub const SPI_MODE: Mode = MODE_0;
     pub const SPI_FREQ: HertzU32 = HertzU32::MHz(9);
+    pub const MAX_VALUE: u16 = 4095;
 
     pub fn new(cs1: PinDac1Cs, cs2: PinDac2Cs) -> Self {
         return Self {
@@ -122,6 +123,33 @@ impl Dac {
         };
     }
 
+    pub fn calibration(&self, channel: DacChannel, level: usize) -> u16 {
+        return self.calibration[channel as usize][level];
+    }
+
+    pub fn calibrate(&mut self, channel: DacChannel, level: usize, value: u16) {
+        self.calibration[channel as usize][level] = value.min(Self::MAX_VALUE);
+    }
+
+    pub fn save(&self, writer: &mut Writer) {
+        for levels in &self.calibration {
+            for &value in levels {
+                writer.u16(value);
+            }
+        }
+    }
+
+    pub fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        let mut calibration = self.calibration;
+        for levels in &mut calibration {
+            for value in levels {
+                *value = reader.u16()?;
+            }
+        }
+        self.calibration = calibration;
+        return Some(());
+    }
+
     pub fn set(&mut self, value: u16, channel: DacChannel, spi: &mut OutputsSpi) {
         let cmd = Command::default().double_gain().value(value);
         match channel {
@@ -1,16 +1,27 @@
 #![allow(dead_code)]
 
+use crate::modes::set_variant;
+use crate::note_stack;
+use crate::storage::{Reader, Writer};
 use fugit::*;
 
+pub use crate::note_stack::{NotePriority, Retrigger};
+pub use crate::tuning::Tuning;
+
 #[derive(Clone, Copy, Debug)]
 pub struct Settings {
     pub voicing: Voicing,
     pub note_priority: NotePriority,
+    pub retrigger: Retrigger,
     pub legato: bool,
     pub trigger_length: TriggerLength,
     pub trigger_scaling: bool,
     pub trigger_shape: TriggerShape,
     pub tuning: Tuning,
+    pub clock_division: ClockDivision,
+    pub glide_mode: GlideMode,
+    pub envelope_velocity: bool,
+    pub omni: bool,
 }
 
 impl Default for Settings {
@@ -18,11 +29,69 @@ impl Default for Settings {
         return Self {
             voicing: Voicing::Poly,
             note_priority: NotePriority::Latest,
+            retrigger: Retrigger::Ignore,
             legato: false,
             trigger_length: TriggerLength::T5ms,
             trigger_scaling: false,
             trigger_shape: TriggerShape::Square,
-            tuning: Tuning::EqualTemperament,
+            tuning: Tuning::default(),
+            clock_division: ClockDivision::Ppqn4,
+            glide_mode: GlideMode::ConstantTime,
+            envelope_velocity: false,
+            omni: false,
+        };
+    }
+}
+
+impl Settings {
+    pub fn save(&self, writer: &mut Writer) {
+        writer.u8(self.voicing as u8);
+        writer.u8(self.note_priority as u8);
+        writer.u8(self.retrigger as u8);
+        writer.bool(self.legato);
+        writer.u8(self.trigger_length as u8);
+        writer.bool(self.trigger_scaling);
+        writer.u8(self.trigger_shape as u8);
+        self.tuning.save(writer);
+        writer.u8(self.clock_division as u8);
+        writer.u8(self.glide_mode as u8);
+        writer.bool(self.envelope_velocity);
+        writer.bool(self.omni);
+    }
+
+    pub fn load(reader: &mut Reader) -> Option<Self> {
+        return Some(Self {
+            voicing: reader.variant(&Voicing::ALL)?,
+            note_priority: reader.variant(&NotePriority::ALL)?,
+            retrigger: reader.variant(&Retrigger::ALL)?,
+            legato: reader.bool()?,
+            trigger_length: reader.variant(&TriggerLength::ALL)?,
+            trigger_scaling: reader.bool()?,
+            trigger_shape: reader.variant(&TriggerShape::ALL)?,
+            tuning: Tuning::load(reader)?,
+            clock_division: reader.variant(&ClockDivision::ALL)?,
+            glide_mode: reader.variant(&GlideMode::ALL)?,
+            envelope_velocity: reader.bool()?,
+            omni: reader.bool()?,
+        });
+    }
+
+    /// Sets one of the settings received as NRPN, returns whether it changed.
+    pub fn set_parameter(&mut self, parameter: u8, value: u8) -> bool {
+        return match parameter {
+            0 => set_variant(&mut self.clock_division, &ClockDivision::ALL, value),
+            1 => set_variant(&mut self.glide_mode, &GlideMode::ALL, value),
+            2 => set_variant(&mut self.envelope_velocity, &[false, true], value),
+            3 => set_variant(&mut self.retrigger, &Retrigger::ALL, value),
+            _ => false,
+        };
+    }
+
+    pub fn note_stack(&self) -> note_stack::Config {
+        return note_stack::Config {
+            priority: self.note_priority,
+            retrigger: self.retrigger,
+            legato: self.legato,
         };
     }
 }
@@ -36,13 +105,8 @@ pub enum Voicing {
     Velocity,
 }
 
-#[repr(u8)]
-#[derive(Clone, Copy, Debug)]
-pub enum NotePriority {
-    Latest,
-    First,
-    Highest,
-    Lowest,
+impl Voicing {
+    pub const ALL: [Self; 4] = [Self::Poly, Self::Cyclic, Self::Random, Self::Velocity];
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -54,14 +118,18 @@ pub enum TriggerLength {
     T25ms,
 }
 
-impl Into<MicrosDurationU32> for TriggerLength {
-    fn into(self) -> MicrosDurationU32 {
-        return match self {
-            Self::T50us  =>  50u32.micros(),
-            Self::T500us => 500u32.micros(),
-            Self::T1ms   =>   1u32.millis(),
-            Self::T5ms   =>   5u32.millis(),
-            Self::T25ms  =>  25u32.millis(),
+impl TriggerLength {
+    pub const ALL: [Self; 5] = [Self::T50us, Self::T500us, Self::T1ms, Self::T5ms, Self::T25ms];
+}
+
+impl From<TriggerLength> for MicrosDurationU32 {
+    fn from(length: TriggerLength) -> Self {
+        return match length {
+            TriggerLength::T50us  =>  50u32.micros(),
+            TriggerLength::T500us => 500u32.micros(),
+            TriggerLength::T1ms   =>   1u32.millis(),
+            TriggerLength::T5ms   =>   5u32.millis(),
+            TriggerLength::T25ms  =>  25u32.millis(),
         };
     }
 }
@@ -72,8 +140,50 @@ pub enum TriggerShape {
     Square,
 }
 
+impl TriggerShape {
+    pub const ALL: [Self; 1] = [Self::Square];
+}
+
 #[repr(u8)]
-#[derive(Clone, Copy, Debug)]
-pub enum Tuning {
-    EqualTemperament,
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ClockDivision {
+    Ppqn24,
+    Ppqn8,
+    Ppqn4,
+    Ppqn2,
+    Ppqn1,
+    Bar,
+}
+
+impl ClockDivision {
+    pub const ALL: [Self; 6] = [
+        Self::Ppqn24,
+        Self::Ppqn8,
+        Self::Ppqn4,
+        Self::Ppqn2,
+        Self::Ppqn1,
+        Self::Bar,
+    ];
+
+    pub fn midi_clocks(self) -> u32 {
+        return match self {
+            Self::Ppqn24 => 1,
+            Self::Ppqn8 => 3,
+            Self::Ppqn4 => 6,
+            Self::Ppqn2 => 12,
+            Self::Ppqn1 => 24,
+            Self::Bar => 96,
+        };
+    }
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GlideMode {
+    ConstantTime,
+    ConstantRate,
+}
+
+impl GlideMode {
+    pub const ALL: [Self; 2] = [Self::ConstantTime, Self::ConstantRate];
 }
@@ -0,0 +1,290 @@
+//! Wear levelled storage of blobs of data in a few pages of flash.
+//!
+//! Every save appends a record to a circular log of pages, so a page is only erased once the
+//! log wraps around to it. A log may take only some of the pages, so data that is saved often
+//! doesn't wear them together with data that rarely changes. The record with the highest
+//! sequence number and a valid CRC is the current one. A record is laid out as
+//!
+//! | version | 0 | length (u16) | sequence (u32) | data, padded to even length | CRC-16 |
+//!
+//! all little endian, and never crosses a page boundary. Erased flash reads as `0xff`, which is
+//! never a valid version.
+
+pub const PAGE_SIZE: usize = 1024;
+pub const N_PAGES: usize = 4;
+pub const VERSION: u8 = 5;
+
+use core::ops::Range;
+
+const HEADER_SIZE: usize = 8;
+const CRC_SIZE: usize = 2;
+pub const MAX_DATA: usize = PAGE_SIZE - HEADER_SIZE - CRC_SIZE;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Error {
+    Flash,
+    TooLarge,
+}
+
+/// The storage pages, addressed from the start of the first one. Writes are half-word aligned
+/// and have an even length.
+pub trait Flash {
+    fn read(&mut self, offset: usize, data: &mut [u8]) -> Result<(), Error>;
+    fn erase_page(&mut self, page: usize) -> Result<(), Error>;
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error>;
+}
+
+#[derive(Debug)]
+pub struct Storage {
+    start: usize,
+    end: usize,
+    next: usize,
+    sequence: u32,
+    /// Offset and length of the current record.
+    latest: Option<(usize, usize)>,
+}
+
+impl Storage {
+    /// Finds the current record of the log on `pages` and copies its data into `data`,
+    /// returning its length if there is one.
+    pub fn open<F: Flash>(
+        flash: &mut F,
+        pages: Range<usize>,
+        data: &mut [u8],
+    ) -> Result<(Self, Option<usize>), Error> {
+        let (start, end) = (pages.start * PAGE_SIZE, pages.end * PAGE_SIZE);
+        let mut latest: Option<(usize, usize, u32)> = None;
+        let mut next = start;
+        for page in pages {
+            let mut offset = page * PAGE_SIZE;
+            let end = offset + PAGE_SIZE;
+            while offset + HEADER_SIZE <= end {
+                let mut header = [0; HEADER_SIZE];
+                flash.read(offset, &mut header)?;
+                if header[0] == 0xff {
+                    break;
+                }
+                let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+                let size = record_size(len);
+                if offset + size > end {
+                    // a torn header, nothing can be appended to this page anymore
+                    offset = end;
+                    break;
+                }
+                let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+                let is_newer = match latest {
+                    Some((_, _, latest)) => sequence > latest,
+                    None => true,
+                };
+                if header[0] == VERSION && is_newer && is_valid(flash, offset, size)? {
+                    latest = Some((offset, len, sequence));
+                }
+                offset += size;
+            }
+            if let Some((start, _, _)) = latest {
+                if start / PAGE_SIZE == page {
+                    next = offset;
+                }
+            }
+        }
+
+        let (len, sequence) = match latest {
+            Some((offset, len, sequence)) if len <= data.len() => {
+                flash.read(offset + HEADER_SIZE, &mut data[..len])?;
+                (Some(len), sequence)
+            },
+            Some((_, _, sequence)) => (None, sequence),
+            None => (None, 0),
+        };
+        let latest = latest.map(|(offset, len, _)| (offset, len));
+        return Ok((Self { start, end, next, sequence, latest }, len));
+    }
+
+    /// Appends a record, unless `data` is what the current one holds already.
+    pub fn save<F: Flash>(&mut self, flash: &mut F, data: &[u8]) -> Result<(), Error> {
+        if data.len() > MAX_DATA {
+            return Err(Error::TooLarge);
+        }
+        if self.is_current(flash, data)? {
+            return Ok(());
+        }
+        let size = record_size(data.len());
+        let page_end = (self.next / PAGE_SIZE + 1) * PAGE_SIZE;
+        if self.next + size > page_end {
+            self.next = page_end;
+        }
+        if self.next >= self.end {
+            self.next = self.start;
+        }
+        if self.next.is_multiple_of(PAGE_SIZE) {
+            flash.erase_page(self.next / PAGE_SIZE)?;
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+        let len = (data.len() as u16).to_le_bytes();
+        let sequence = self.sequence.to_le_bytes();
+        let header =
+            [VERSION, 0, len[0], len[1], sequence[0], sequence[1], sequence[2], sequence[3]];
+        let even = data.len() & !1;
+        let padding = match data.len() % 2 {
+            0 => None,
+            _ => Some([data[even], 0]),
+        };
+        let mut crc = crc16(0xffff, &header);
+        crc = crc16(crc, &data[..even]);
+        if let Some(padding) = &padding {
+            crc = crc16(crc, padding);
+        }
+
+        let mut offset = self.next;
+        flash.write(offset, &header)?;
+        offset += HEADER_SIZE;
+        flash.write(offset, &data[..even])?;
+        offset += even;
+        if let Some(padding) = &padding {
+            flash.write(offset, padding)?;
+            offset += 2;
+        }
+        flash.write(offset, &crc.to_le_bytes())?;
+        self.latest = Some((self.next, data.len()));
+        self.next = offset + CRC_SIZE;
+        return Ok(());
+    }
+
+    fn is_current<F: Flash>(&self, flash: &mut F, data: &[u8]) -> Result<bool, Error> {
+        let offset = match self.latest {
+            Some((offset, len)) if len == data.len() => offset + HEADER_SIZE,
+            _ => return Ok(false),
+        };
+        let mut chunk = [0; 32];
+        let size = chunk.len();
+        for (i, expected) in data.chunks(size).enumerate() {
+            let stored = &mut chunk[..expected.len()];
+            flash.read(offset + i * size, stored)?;
+            if stored != expected {
+                return Ok(false);
+            }
+        }
+        return Ok(true);
+    }
+}
+
+fn record_size(len: usize) -> usize {
+    return HEADER_SIZE + len + len % 2 + CRC_SIZE;
+}
+
+fn is_valid<F: Flash>(flash: &mut F, offset: usize, size: usize) -> Result<bool, Error> {
+    let mut crc = 0xffff;
+    let mut chunk = [0; 32];
+    let end = offset + size - CRC_SIZE;
+    let mut position = offset;
+    while position < end {
+        let len = chunk.len().min(end - position);
+        flash.read(position, &mut chunk[..len])?;
+        crc = crc16(crc, &chunk[..len]);
+        position += len;
+    }
+    let mut stored = [0; CRC_SIZE];
+    flash.read(end, &mut stored)?;
+    return Ok(u16::from_le_bytes(stored) == crc);
+}
+
+/// CRC-16/CCITT-FALSE, continuing from `crc`.
+fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    return crc;
+}
+
+/// Serializes settings into a record.
+#[derive(Debug)]
+pub struct Writer {
+    data: [u8; MAX_DATA],
+    len: usize,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        return Self { data: [0; MAX_DATA], len: 0 };
+    }
+}
+
+impl Writer {
+    /// The serialized data, or an error if it didn't fit.
+    pub fn as_bytes(&self) -> Result<&[u8], Error> {
+        return self.data.get(..self.len).ok_or(Error::TooLarge);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        // keep counting past the end, so the overflow shows in `as_bytes`
+        if let Some(byte) = self.data.get_mut(self.len) {
+            *byte = value;
+        }
+        self.len += 1;
+    }
+
+    pub fn i8(&mut self, value: i8) {
+        self.u8(value as u8);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        for byte in value.to_le_bytes() {
+            self.u8(byte);
+        }
+    }
+
+    /// Writes an optional enum index, with `0xff` for `None`.
+    pub fn option(&mut self, value: Option<u8>) {
+        self.u8(value.unwrap_or(0xff));
+    }
+}
+
+/// Deserializes settings from a record, every read fails once the data is exhausted.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        return Self { data };
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        let (&value, rest) = self.data.split_first()?;
+        self.data = rest;
+        return Some(value);
+    }
+
+    pub fn i8(&mut self) -> Option<i8> {
+        return self.u8().map(|value| value as i8);
+    }
+
+    pub fn bool(&mut self) -> Option<bool> {
+        return self.u8().map(|value| value != 0);
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        return Some(u16::from_le_bytes([self.u8()?, self.u8()?]));
+    }
+
+    /// Reads an enum written as its index into `variants`.
+    pub fn variant<T: Copy>(&mut self, variants: &[T]) -> Option<T> {
+        return variants.get(self.u8()? as usize).copied();
+    }
+
+    pub fn option<T: Copy>(&mut self, variants: &[T]) -> Option<Option<T>> {
+        return match self.u8()? {
+            0xff => Some(None),
+            index => variants.get(index as usize).map(|&variant| Some(variant)),
+        };
+    }
+}
@@ -0,0 +1,345 @@
+//! Microtonal tunings, which map MIDI notes to pitches in fractional equal tempered semitones.
+//!
+//! The tuning is selected with NRPN MSB (CC 99) `0x50` and the NRPN LSB (CC 98) below, the
+//! value is sent as Data Entry MSB (CC 6) on any channel.
+//!
+//! | LSB | parameter                                   |
+//! |-----|---------------------------------------------|
+//! | 0   | temperament, in the order of [`Temperament`] |
+//! | 1   | steps per octave of EDO, 5 to 31            |
+//! | 2   | tonic, 0 (C) to 11 (B)                      |
+//!
+//! The table is uploaded with MIDI Tuning Standard bulk dumps and single note tuning changes,
+//! for any device ID and tuning program. Changes apply from the next note on.
+
+use crate::midi::{ParameterNumber, DATA_ENTRY_MSB_CC};
+use crate::storage::{Reader, Writer};
+
+use embedded_midi::MidiMessage as Midi;
+use fixed::types::I16F16;
+
+pub const NRPN_MSB: u8 = 0x50;
+
+pub const N_NOTES: usize = 128;
+pub const MIN_EDO: u8 = 5;
+pub const MAX_EDO: u8 = 31;
+/// EDO tunings keep the tonic above middle C in place.
+const EDO_ANCHOR: i32 = 60;
+
+// deviations from equal temperament in hundredths of a cent, starting at the tonic
+const JUST: [i16; 12] = [0, 1173, 391, 1564, -1369, -196, -978, 196, 1369, -1564, 1760, -1173];
+const PYTHAGOREAN: [i16; 12] = [0, -978, 391, -587, 782, -196, 1173, 196, -782, 587, -391, 978];
+const MEANTONE: [i16; 12] =
+    [0, -2395, -684, 1026, -1369, 342, -2053, -342, -2737, -1026, 684, -1711];
+
+const SYSEX_START: u8 = 0xf0;
+const SYSEX_END: u8 = 0xf7;
+const NON_REAL_TIME: u8 = 0x7e;
+const REAL_TIME: u8 = 0x7f;
+const MIDI_TUNING: u8 = 0x08;
+const BULK_DUMP: u8 = 0x01;
+const SINGLE_NOTE: u8 = 0x02;
+const SINGLE_NOTE_BANK: u8 = 0x07;
+const NO_CHANGE: [u8; 3] = [0x7f, 0x7f, 0x7f];
+
+// byte offsets after the sysex start
+const BULK_DATA: usize = 21;
+const BULK_CHECKSUM: usize = BULK_DATA + 3 * N_NOTES;
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Temperament {
+    Equal,
+    /// 5-limit just intonation.
+    Just,
+    Pythagorean,
+    /// Quarter-comma meantone.
+    Meantone,
+    /// Equal divisions of the octave, one step per key.
+    Edo,
+    /// The table uploaded with the MIDI Tuning Standard.
+    Table,
+}
+
+impl Temperament {
+    pub const ALL: [Self; 6] = [
+        Self::Equal,
+        Self::Just,
+        Self::Pythagorean,
+        Self::Meantone,
+        Self::Edo,
+        Self::Table,
+    ];
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Tuning {
+    pub temperament: Temperament,
+    /// Pitch class the temperament starts at, 0 for C.
+    pub tonic: u8,
+    /// Steps per octave of [`Temperament::Edo`].
+    pub edo: u8,
+}
+
+impl Default for Tuning {
+    fn default() -> Self {
+        return Self { temperament: Temperament::Equal, tonic: 0, edo: 12 };
+    }
+}
+
+impl Tuning {
+    pub fn save(&self, writer: &mut Writer) {
+        writer.u8(self.temperament as u8);
+        writer.u8(self.tonic);
+        writer.u8(self.edo);
+    }
+
+    pub fn load(reader: &mut Reader) -> Option<Self> {
+        return Some(Self {
+            temperament: reader.variant(&Temperament::ALL)?,
+            tonic: reader.u8().filter(|&tonic| tonic < 12)?,
+            edo: reader.u8().filter(|edo| (MIN_EDO..=MAX_EDO).contains(edo))?,
+        });
+    }
+
+    /// The pitch of a possibly gliding note, interpolated between the notes around it.
+    pub fn pitch(&self, note: I16F16, table: &Table) -> I16F16 {
+        let index = note.floor().to_num::<i32>().clamp(0, N_NOTES as i32 - 2);
+        let fraction = note - I16F16::from_num(index);
+        let x0 = self.note_pitch(index as u8, table);
+        let x1 = self.note_pitch(index as u8 + 1, table);
+        return fraction.lerp(x0, x1);
+    }
+
+    fn note_pitch(&self, note: u8, table: &Table) -> I16F16 {
+        let degree = (note as i32 - self.tonic as i32).rem_euclid(12) as usize;
+        let deviation = |cents: [i16; 12]| I16F16::from_num(cents[degree]) / 10_000;
+        let pitch = I16F16::from_num(note);
+        return match self.temperament {
+            Temperament::Equal => pitch,
+            Temperament::Just => pitch + deviation(JUST),
+            Temperament::Pythagorean => pitch + deviation(PYTHAGOREAN),
+            Temperament::Meantone => pitch + deviation(MEANTONE),
+            Temperament::Edo => {
+                let anchor = EDO_ANCHOR + self.tonic as i32;
+                let steps = I16F16::from_num(note as i32 - anchor);
+                I16F16::from_num(anchor) + steps * 12 / self.edo as i32
+            },
+            Temperament::Table => table.pitch(note),
+        };
+    }
+}
+
+/// Pitch of every note, as uploaded with the MIDI Tuning Standard.
+#[derive(Clone, Copy, Debug)]
+pub struct Table {
+    pitches: [I16F16; N_NOTES],
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        let mut pitches = [I16F16::ZERO; N_NOTES];
+        for (note, pitch) in pitches.iter_mut().enumerate() {
+            *pitch = I16F16::from_num(note);
+        }
+        return Self { pitches };
+    }
+}
+
+impl Table {
+    pub fn pitch(&self, note: u8) -> I16F16 {
+        return self.pitches[note as usize];
+    }
+
+    pub fn set_pitch(&mut self, note: u8, pitch: I16F16) {
+        self.pitches[note as usize] = pitch;
+    }
+
+    /// Sets a note from MTS frequency data, a semitone and a 14 bit fraction of it.
+    fn set_frequency_data(&mut self, note: u8, data: [u8; 3]) {
+        if data == NO_CHANGE {
+            return;
+        }
+        let fraction = (data[1] as i32) << 7 | data[2] as i32;
+        self.pitches[note as usize] = I16F16::from_bits((data[0] as i32) << 16 | fraction << 2);
+    }
+
+    pub fn save(&self, writer: &mut Writer) {
+        for pitch in &self.pitches {
+            writer.u8((pitch.to_bits() >> 16) as u8);
+            writer.u16(pitch.to_bits() as u16);
+        }
+    }
+
+    pub fn load(&mut self, reader: &mut Reader) -> Option<()> {
+        let mut pitches = self.pitches;
+        for pitch in &mut pitches {
+            let (semitone, fraction) = (reader.u8()? as i32, reader.u16()? as i32);
+            *pitch = I16F16::from_bits(semitone << 16 | fraction);
+        }
+        self.pitches = pitches;
+        return Some(());
+    }
+}
+
+/// Selects the tuning with NRPNs.
+#[derive(Default, Debug)]
+pub struct Selector {
+    number: ParameterNumber,
+}
+
+impl Selector {
+    /// Returns whether `tuning` changed.
+    pub fn handle_midi_event(&mut self, msg: Midi, tuning: &mut Tuning) -> bool {
+        let (cc, value): (u8, u8) = match msg {
+            Midi::ControlChange(_, cc, value) => (cc.into(), value.into()),
+            _ => return false,
+        };
+        let parameter = match self.number.nrpn() {
+            Some((NRPN_MSB, parameter)) if cc == DATA_ENTRY_MSB_CC => parameter,
+            _ => {
+                self.number.handle_cc(cc, value);
+                return false;
+            },
+        };
+        let last = *tuning;
+        match parameter {
+            0 => {
+                if let Some(&temperament) = Temperament::ALL.get(value as usize) {
+                    tuning.temperament = temperament;
+                }
+            },
+            1 => tuning.edo = value.clamp(MIN_EDO, MAX_EDO),
+            2 => tuning.tonic = value % 12,
+            _ => (),
+        }
+        return *tuning != last;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TableUpdate {
+    /// A complete table, worth saving.
+    BulkDump,
+    /// A single note changed in real time.
+    SingleNote,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Idle,
+    Header,
+    BulkDump,
+    /// Note changes starting at the given offset.
+    SingleNote(usize),
+    Ignore,
+}
+
+/// Receives MIDI Tuning Standard messages from the raw MIDI bytes, as the parser skips sysex.
+#[derive(Debug)]
+pub struct MtsReceiver {
+    state: State,
+    /// Data bytes received since the sysex start.
+    len: usize,
+    header: [u8; 4],
+    entry: [u8; 4],
+    checksum: u8,
+    /// Bulk dumps are only applied once their checksum is verified.
+    pending: Table,
+}
+
+impl Default for MtsReceiver {
+    fn default() -> Self {
+        return Self {
+            state: State::Idle,
+            len: 0,
+            header: [0; 4],
+            entry: [0; 4],
+            checksum: 0,
+            pending: Table::default(),
+        };
+    }
+}
+
+impl MtsReceiver {
+    pub fn parse_byte(&mut self, byte: u8, table: &mut Table) -> Option<TableUpdate> {
+        match byte {
+            // real time messages may be interleaved with sysex
+            0xf8..=0xff => return None,
+            SYSEX_START => {
+                self.state = State::Header;
+                self.len = 0;
+                self.checksum = 0;
+                return None;
+            },
+            SYSEX_END => {
+                let is_complete = self.state == State::BulkDump
+                    && self.len == BULK_CHECKSUM + 1
+                    && self.checksum == 0;
+                self.state = State::Idle;
+                if is_complete {
+                    *table = self.pending;
+                    return Some(TableUpdate::BulkDump);
+                }
+                return None;
+            },
+            0x80..=0xff => {
+                self.state = State::Idle;
+                return None;
+            },
+            _ => (),
+        }
+
+        let offset = self.len;
+        self.len += 1;
+        self.checksum ^= byte;
+        match self.state {
+            State::Header => {
+                self.header[offset] = byte;
+                if offset == self.header.len() - 1 {
+                    self.state = self.message_state(table);
+                }
+            },
+            State::BulkDump if (BULK_DATA..BULK_CHECKSUM).contains(&offset) => {
+                let index = (offset - BULK_DATA) % 3;
+                self.entry[index] = byte;
+                if index == 2 {
+                    let note = ((offset - BULK_DATA) / 3) as u8;
+                    let data = [self.entry[0], self.entry[1], self.entry[2]];
+                    self.pending.set_frequency_data(note, data);
+                }
+            },
+            State::SingleNote(start) if offset >= start => {
+                let index = (offset - start) % 4;
+                self.entry[index] = byte;
+                if index == 3 {
+                    let data = [self.entry[1], self.entry[2], self.entry[3]];
+                    table.set_frequency_data(self.entry[0], data);
+                    return Some(TableUpdate::SingleNote);
+                }
+            },
+            _ => (),
+        }
+        return None;
+    }
+
+    /// Decides what to do with a message from its universal sysex header.
+    fn message_state(&mut self, table: &Table) -> State {
+        let [kind, _device, sub_id, message] = self.header;
+        if sub_id != MIDI_TUNING {
+            return State::Ignore;
+        }
+        return match (kind, message) {
+            (NON_REAL_TIME, BULK_DUMP) => {
+                // notes left unchanged keep their current pitch
+                self.pending = *table;
+                State::BulkDump
+            },
+            // after the program and the number of changes
+            (REAL_TIME, SINGLE_NOTE) => State::SingleNote(6),
+            // after the bank, the program and the number of changes
+            (NON_REAL_TIME | REAL_TIME, SINGLE_NOTE_BANK) => State::SingleNote(7),
+            _ => State::Ignore,
+        };
+    }
+}
@@ -0,0 +1,116 @@
+#![allow(clippy::needless_return)]
+
+extern crate embedded_midi;
+extern crate etas_midi2cv_firmware;
+
+use etas_midi2cv_firmware::calibration::{
+    Command, Remote, CORRECTION_NRPN_MSB, NRPN_MSB, TABLE_NRPN_MSB,
+};
+use etas_midi2cv_firmware::context::Menu;
+
+use embedded_midi::MidiMessage as Midi;
+
+fn cc(channel: u8, cc: u8, value: u8) -> Midi {
+    return Midi::ControlChange(channel.into(), cc.into(), value.into());
+}
+
+fn send(remote: &mut Remote, parameter: u8, value: u16) -> Vec<Command> {
+    return send_nrpn(remote, NRPN_MSB, parameter, value);
+}
+
+fn send_nrpn(remote: &mut Remote, msb: u8, parameter: u8, value: u16) -> Vec<Command> {
+    return send_in_menu(remote, Menu::Calibration, (msb, parameter), value);
+}
+
+fn send_in_menu(
+    remote: &mut Remote,
+    menu: Menu,
+    (msb, lsb): (u8, u8),
+    value: u16,
+) -> Vec<Command> {
+    let messages = [
+        cc(15, 99, msb),
+        cc(15, 98, lsb),
+        cc(15, 6, (value >> 7) as u8),
+        cc(15, 38, (value & 0x7f) as u8),
+    ];
+    return messages.iter().filter_map(|&msg| remote.handle_midi_event(msg, menu)).collect();
+}
+
+#[test]
+fn parameters() {
+    let mut remote = Remote::default();
+    assert_eq!(send(&mut remote, 0, 3), [Command::SelectChannel(3)]);
+    assert_eq!(send(&mut remote, 1, 8), [Command::SelectLevel(8)]);
+    assert_eq!(send(&mut remote, 2, 4000), [Command::Set(4000)]);
+    assert_eq!(send(&mut remote, 3, 8192 - 5), [Command::Offset(-5)]);
+    assert_eq!(send(&mut remote, 3, 8192 + 40), [Command::Offset(40)]);
+    assert_eq!(send(&mut remote, 4, 0), [Command::SendTable]);
+    assert_eq!(send(&mut remote, 5, 0), [Command::Save]);
+    assert_eq!(send(&mut remote, 6, 8192 - 12), [Command::FineTune(-12)]);
+    assert_eq!(send(&mut remote, 7, 2), [Command::Standard(2)]);
+    assert_eq!(send(&mut remote, 8, 36), [Command::Root(36)]);
+    assert_eq!(send(&mut remote, 9, 1035), [Command::Millivolts(1035)]);
+    assert_eq!(send(&mut remote, 10, 0), []);
+}
+
+#[test]
+fn out_of_range_values_are_rejected() {
+    let mut remote = Remote::default();
+    assert_eq!(send(&mut remote, 0, 4), []);
+    assert_eq!(send(&mut remote, 0, 256), []);
+    assert_eq!(send(&mut remote, 1, 265), [Command::SelectLevel(255)]);
+    assert_eq!(send(&mut remote, 9, 0), []);
+}
+
+#[test]
+fn commands_are_only_taken_in_calibration() {
+    let mut remote = Remote::default();
+    for menu in [Menu::Main, Menu::MidiLearn, Menu::Settings, Menu::SettingEdit] {
+        assert_eq!(send_in_menu(&mut remote, menu, (NRPN_MSB, 0), 1), []);
+    }
+    let command = [Command::SelectChannel(1)];
+    assert_eq!(send_in_menu(&mut remote, Menu::CalibrationEdit, (NRPN_MSB, 0), 1), command);
+}
+
+#[test]
+fn semitone_corrections() {
+    let mut remote = Remote::default();
+    let command = Command::Correct { channel: 0, semitone: 30, cents: 3 };
+    assert_eq!(send_nrpn(&mut remote, CORRECTION_NRPN_MSB, 30, 8192 + 3), [command]);
+    let command = Command::Correct { channel: 3, semitone: 96, cents: -7 };
+    assert_eq!(send_nrpn(&mut remote, CORRECTION_NRPN_MSB + 3, 96, 8192 - 7), [command]);
+    assert_eq!(send_nrpn(&mut remote, CORRECTION_NRPN_MSB + 4, 0, 8192), []);
+}
+
+#[test]
+fn other_parameters_are_ignored() {
+    let mut remote = Remote::default();
+    assert_eq!(remote.handle_midi_event(cc(0, 38, 1), Menu::Calibration), None);
+
+    // pitch bend sensitivity
+    let messages = [cc(0, 101, 0), cc(0, 100, 0), cc(0, 6, 2), cc(0, 38, 0)];
+    for msg in messages {
+        assert_eq!(remote.handle_midi_event(msg, Menu::Calibration), None);
+    }
+
+    send(&mut remote, 2, 100);
+    assert_eq!(remote.handle_midi_event(cc(0, 101, 0), Menu::Calibration), None);
+    assert_eq!(remote.handle_midi_event(cc(0, 38, 1), Menu::Calibration), None);
+}
+
+#[test]
+fn table_entries_reply_on_command_channel() {
+    let mut remote = Remote::default();
+    send(&mut remote, 4, 0);
+    let entry = remote.table_entry(2, 7, 3500);
+    assert_eq!(
+        entry,
+        [
+            cc(15, 99, TABLE_NRPN_MSB + 2),
+            cc(15, 98, 7),
+            cc(15, 6, (3500 >> 7) as u8),
+            cc(15, 38, (3500 & 0x7f) as u8),
+        ]
+    );
+}
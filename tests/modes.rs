@@ -0,0 +1,572 @@
+#![allow(clippy::needless_return)]
+
+extern crate embedded_midi;
+extern crate etas_midi2cv_firmware;
+extern crate fixed;
+extern crate fugit;
+
+use etas_midi2cv_firmware::io::{Cv, Gate};
+use etas_midi2cv_firmware::mock::{Event, MockOutputs};
+use etas_midi2cv_firmware::modes::*;
+use etas_midi2cv_firmware::settings::Settings;
+
+use embedded_midi::MidiMessage as Midi;
+use fixed::types::U16F16;
+use fugit::*;
+
+fn note_on(channel: u8, note: u8, velocity: u8) -> Midi {
+    return Midi::NoteOn(channel.into(), note.into(), velocity.into());
+}
+
+fn note_off(channel: u8, note: u8) -> Midi {
+    return Midi::NoteOff(channel.into(), note.into(), 0.into());
+}
+
+fn cc(channel: u8, cc: u8, value: u8) -> Midi {
+    return Midi::ControlChange(channel.into(), cc.into(), value.into());
+}
+
+/// Sends `value` to the NRPN `msb`, `lsb`, returns whether the settings changed.
+fn send_nrpn(
+    mode: &mut dyn Mode<MockOutputs>,
+    outputs: &mut MockOutputs,
+    settings: &mut Settings,
+    (msb, lsb): (u8, u8),
+    value: u8,
+) -> bool {
+    let mut parameters = Parameters::default();
+    parameters.handle_midi_event(cc(0, 99, msb), mode, outputs, settings);
+    parameters.handle_midi_event(cc(0, 98, lsb), mode, outputs, settings);
+    return parameters.handle_midi_event(cc(0, 6, value), mode, outputs, settings);
+}
+
+/// Sends a mode parameter as NRPN, returns whether the settings changed.
+fn set_parameter(
+    mode: &mut dyn Mode<MockOutputs>,
+    outputs: &mut MockOutputs,
+    parameter: u8,
+    value: u8,
+) -> bool {
+    let settings = &mut Settings::default();
+    return send_nrpn(mode, outputs, settings, (PARAMETER_NRPN_MSB, parameter), value);
+}
+
+/// Sends a global setting as NRPN, returns whether it changed.
+fn set_setting(settings: &mut Settings, setting: u8, value: u8) -> bool {
+    let (mode, outputs) = (&mut Mono::default(), &mut MockOutputs::default());
+    return send_nrpn(mode, outputs, settings, (SETTING_NRPN_MSB, setting), value);
+}
+
+fn volts(note: u8) -> U16F16 {
+    return U16F16::from_num(note - 24) / 12;
+}
+
+/// Runs the arpeggiator for a second, returns the number of steps it played.
+fn arp_steps(arp: &mut Arp, outputs: &mut MockOutputs) -> usize {
+    let mut steps = 0;
+    for _ in 0..1000 {
+        let was_open = outputs.gate(Gate::G1);
+        arp.update(1u32.millis(), outputs);
+        if outputs.gate(Gate::G1) && !was_open {
+            steps += 1;
+        }
+    }
+    return steps;
+}
+
+#[test]
+fn mono_plays_latest_note() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut mono = Mono::default();
+
+    mono.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    assert!(outputs.gate(Gate::G1));
+    assert!(outputs.gate(Gate::G2));
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(60));
+
+    mono.handle_midi_event(note_on(0, 67, 100), &mut outputs, &settings);
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(67));
+    mono.handle_midi_event(note_off(0, 67), &mut outputs, &settings);
+    assert!(outputs.gate(Gate::G1));
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(60));
+
+    mono.handle_midi_event(note_off(0, 60), &mut outputs, &settings);
+    assert!(!outputs.gate(Gate::G1));
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(60));
+}
+
+#[test]
+fn mono_ignores_other_channels() {
+    let mut settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut mono = Mono::default();
+
+    mono.handle_midi_event(note_on(3, 60, 100), &mut outputs, &settings);
+    assert!(outputs.take_events().is_empty());
+
+    settings.omni = true;
+    mono.handle_midi_event(note_on(3, 60, 100), &mut outputs, &settings);
+    assert!(outputs.gate(Gate::G1));
+}
+
+#[test]
+fn trigger_ends_after_trigger_length() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut drum = Drum::default();
+
+    drum.handle_midi_event(note_on(9, 38, 127), &mut outputs, &settings);
+    assert_eq!(
+        outputs.take_events(),
+        [Event::Gate(Gate::G2, true), Event::Cv(Cv::Cv2, U16F16::from_num(8))]
+    );
+    drum.update(4u32.millis(), &mut outputs);
+    assert!(outputs.gate(Gate::G2));
+    drum.update(2u32.millis(), &mut outputs);
+    assert!(!outputs.gate(Gate::G2));
+}
+
+#[test]
+fn poly_allocates_free_voices() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut poly = Poly::default();
+
+    for &note in &[60, 64, 67] {
+        poly.handle_midi_event(note_on(0, note, 100), &mut outputs, &settings);
+    }
+    poly.handle_midi_event(note_off(0, 64), &mut outputs, &settings);
+    poly.handle_midi_event(note_on(0, 71, 100), &mut outputs, &settings);
+
+    let gates = [Gate::G1, Gate::G2, Gate::G3, Gate::G4];
+    assert_eq!(gates.map(|gate| outputs.gate(gate)), [true, true, true, false]);
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(60));
+    assert_eq!(outputs.voltage(Cv::Cv2), volts(71));
+    assert_eq!(outputs.voltage(Cv::Cv3), volts(67));
+}
+
+#[test]
+fn poly_steals_voices_with_a_new_gate() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut poly = Poly::default();
+
+    for &note in &[60, 64, 67, 71] {
+        poly.handle_midi_event(note_on(0, note, 100), &mut outputs, &settings);
+    }
+    outputs.take_events();
+    poly.handle_midi_event(note_on(0, 74, 100), &mut outputs, &settings);
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(74));
+    assert!(outputs.take_events().contains(&Event::Gate(Gate::G1, false)));
+    poly.update(1u32.millis(), &mut outputs);
+    assert!(outputs.take_events().is_empty());
+    poly.update(10u32.millis(), &mut outputs);
+    assert_eq!(outputs.take_events(), [Event::Gate(Gate::G1, true)]);
+}
+
+#[test]
+fn poly_pedals_hold_released_voices() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut poly = Poly::default();
+    let gates = [Gate::G1, Gate::G2, Gate::G3, Gate::G4];
+
+    poly.handle_midi_event(cc(0, 64, 127), &mut outputs, &settings);
+    poly.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    poly.handle_midi_event(note_on(0, 64, 100), &mut outputs, &settings);
+    poly.handle_midi_event(note_off(0, 60), &mut outputs, &settings);
+    assert_eq!(gates.map(|gate| outputs.gate(gate)), [true, true, false, false]);
+    poly.handle_midi_event(cc(0, 64, 0), &mut outputs, &settings);
+    assert_eq!(gates.map(|gate| outputs.gate(gate)), [false, true, false, false]);
+
+    // sostenuto only holds the voices playing when it is pressed
+    poly.handle_midi_event(note_on(0, 67, 100), &mut outputs, &settings);
+    poly.handle_midi_event(cc(0, 66, 127), &mut outputs, &settings);
+    poly.handle_midi_event(note_on(0, 72, 100), &mut outputs, &settings);
+    for &note in &[64, 67, 72] {
+        poly.handle_midi_event(note_off(0, note), &mut outputs, &settings);
+    }
+    assert_eq!(gates.map(|gate| outputs.gate(gate)), [true, true, false, false]);
+    poly.handle_midi_event(cc(0, 66, 0), &mut outputs, &settings);
+    assert_eq!(gates.map(|gate| outputs.gate(gate)), [false; 4]);
+}
+
+#[test]
+fn reset_closes_all_gates() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut poly = Poly::default();
+
+    for &note in &[60, 64, 67, 71] {
+        poly.handle_midi_event(note_on(0, note, 100), &mut outputs, &settings);
+    }
+    Mode::<MockOutputs>::reset(&mut poly, &mut outputs);
+    for gate in 0..4 {
+        assert!(!outputs.gate(Gate::from(gate)));
+    }
+}
+
+#[test]
+fn clock_pulses_on_division() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut clock = Clock::default();
+    let gates = ClockGates { clock: Some(Gate::G5), reset: Some(Gate::G6), run: None };
+    clock.set_gates(gates, &mut outputs);
+
+    // clock messages are ignored while stopped
+    clock.handle_midi_event(Midi::TimingClock, &mut outputs, &settings);
+    assert!(outputs.take_events().is_empty());
+
+    clock.handle_midi_event(Midi::Start, &mut outputs, &settings);
+    assert_eq!(outputs.take_events(), [Event::Gate(Gate::G6, true)]);
+
+    let mut pulses = 0;
+    for _ in 0..24 {
+        clock.handle_midi_event(Midi::TimingClock, &mut outputs, &settings);
+        if outputs.gate(Gate::G5) {
+            pulses += 1;
+        }
+        clock.update(10u32.millis(), &mut outputs);
+    }
+    assert_eq!(pulses, 24 / settings.clock_division.midi_clocks());
+}
+
+#[test]
+fn clock_division_is_a_setting() {
+    let mut settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut clock = Clock::default();
+    let gates = ClockGates { clock: Some(Gate::G5), reset: None, run: None };
+    clock.set_gates(gates, &mut outputs);
+
+    // 24 PPQN, other NRPNs are left alone
+    assert!(set_setting(&mut settings, 0, 0));
+    assert!(!set_setting(&mut settings, 0, 0));
+    assert!(!set_setting(&mut settings, 0, 6));
+    assert!(!set_setting(&mut settings, 127, 0));
+    clock.handle_midi_event(Midi::Start, &mut outputs, &settings);
+    let mut pulses = 0;
+    for _ in 0..24 {
+        clock.handle_midi_event(Midi::TimingClock, &mut outputs, &settings);
+        if outputs.gate(Gate::G5) {
+            pulses += 1;
+        }
+        clock.update(10u32.millis(), &mut outputs);
+    }
+    assert_eq!(pulses, 24);
+}
+
+#[test]
+fn clock_releases_its_gates_on_mode_change() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut clock = Clock::default();
+    let (mono, arp, drum) = (Mono::default(), Arp::default(), Drum::default());
+    clock.set_gates(Mode::<MockOutputs>::clock_gates(&mono), &mut outputs);
+
+    clock.handle_midi_event(Midi::Start, &mut outputs, &settings);
+    clock.handle_midi_event(Midi::TimingClock, &mut outputs, &settings);
+    assert!(outputs.gate(Gate::G3) && outputs.gate(Gate::G5) && outputs.gate(Gate::G6));
+
+    // the run gate is kept when the next mode uses it too
+    clock.set_gates(Mode::<MockOutputs>::clock_gates(&arp), &mut outputs);
+    assert!(outputs.gate(Gate::G3));
+    assert!(!outputs.gate(Gate::G5) && !outputs.gate(Gate::G6));
+
+    clock.handle_midi_event(Midi::Start, &mut outputs, &settings);
+    clock.set_gates(Mode::<MockOutputs>::clock_gates(&drum), &mut outputs);
+    assert!(!outputs.gate(Gate::G3) && !outputs.gate(Gate::G6));
+
+    // nothing is driven without gates
+    outputs.take_events();
+    clock.handle_midi_event(Midi::Start, &mut outputs, &settings);
+    clock.handle_midi_event(Midi::TimingClock, &mut outputs, &settings);
+    clock.update(10u32.millis(), &mut outputs);
+    assert!(outputs.take_events().is_empty());
+}
+
+#[test]
+fn glide_mode_is_a_setting() {
+    let mut settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut mono = Mono::default();
+
+    // constant rate
+    assert!(set_setting(&mut settings, 1, 1));
+    assert!(!set_setting(&mut settings, 1, 2));
+
+    // two octaves take twice the portamento time
+    mono.handle_midi_event(cc(0, 5, 20), &mut outputs, &settings);
+    mono.handle_midi_event(note_on(0, 48, 100), &mut outputs, &settings);
+    mono.update(1u32.secs(), &mut outputs);
+    mono.handle_midi_event(note_on(0, 72, 100), &mut outputs, &settings);
+    mono.update(150u32.millis(), &mut outputs);
+    assert!(outputs.voltage(Cv::Cv1) < volts(72));
+    mono.update(50u32.millis(), &mut outputs);
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(72));
+}
+
+#[test]
+fn retrigger_is_a_setting() {
+    let mut settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut mono = Mono::default();
+
+    // a held note is ignored by default
+    mono.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    outputs.take_events();
+    mono.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    assert!(outputs.take_events().is_empty());
+
+    // and reopens the gate after a trigger length
+    assert!(set_setting(&mut settings, 3, 2));
+    assert!(!set_setting(&mut settings, 3, 4));
+    mono.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    assert!(!outputs.gate(Gate::G1));
+    mono.update(10u32.millis(), &mut outputs);
+    assert!(outputs.gate(Gate::G1));
+}
+
+#[test]
+fn multi_lanes_have_their_own_priority() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut multi = Multi::default();
+
+    // lowest note priority on the second lane
+    assert!(set_parameter(&mut multi, &mut outputs, 1, 3));
+    assert!(!set_parameter(&mut multi, &mut outputs, 1, 3));
+    assert!(!set_parameter(&mut multi, &mut outputs, 1, 4));
+    assert!(!set_parameter(&mut multi, &mut outputs, 4, 0));
+
+    for &note in &[60, 55, 67] {
+        multi.handle_midi_event(note_on(0, note, 100), &mut outputs, &settings);
+        multi.handle_midi_event(note_on(1, note, 100), &mut outputs, &settings);
+    }
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(67));
+    assert_eq!(outputs.voltage(Cv::Cv2), volts(55));
+}
+
+#[test]
+fn split_zones_are_transposed() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut split = Split::default();
+
+    // the lower zone an octave up, the upper zone with lowest note priority
+    assert!(set_parameter(&mut split, &mut outputs, 0, 65));
+    assert!(set_parameter(&mut split, &mut outputs, 3, 3));
+    assert!(!set_parameter(&mut split, &mut outputs, 5, 0));
+
+    split.handle_midi_event(note_on(0, 48, 100), &mut outputs, &settings);
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(60));
+    for &note in &[67, 64, 72] {
+        split.handle_midi_event(note_on(0, note, 100), &mut outputs, &settings);
+    }
+    assert_eq!(outputs.voltage(Cv::Cv2), volts(64));
+
+    // changing the transposition releases the zone
+    assert!(set_parameter(&mut split, &mut outputs, 0, 63));
+    assert!(!outputs.gate(Gate::G1));
+    assert!(outputs.gate(Gate::G2));
+    split.handle_midi_event(note_on(0, 59, 100), &mut outputs, &settings);
+    assert_eq!(outputs.voltage(Cv::Cv1), volts(47));
+}
+
+#[test]
+fn pitch_bend_range_is_set_with_an_rpn() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut mono = Mono::default();
+    let bend_up = Midi::PitchBendChange(0.into(), (0x7f, 0x7f).into());
+    mono.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+
+    // selecting an NRPN deselects the RPN
+    for msg in [cc(0, 101, 0), cc(0, 100, 0), cc(0, 99, 0x60), cc(0, 6, 12), bend_up] {
+        mono.handle_midi_event(msg, &mut outputs, &settings);
+    }
+    assert!(outputs.voltage(Cv::Cv1) < volts(63));
+
+    for msg in [cc(0, 101, 0), cc(0, 100, 0), cc(0, 6, 12), bend_up] {
+        mono.handle_midi_event(msg, &mut outputs, &settings);
+    }
+    assert!(outputs.voltage(Cv::Cv1) > volts(71));
+}
+
+#[test]
+fn arp_follows_its_settings() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut arp = Arp::default();
+
+    // down over two octaves, in quarter notes at 150 BPM with a 25% gate
+    assert!(set_parameter(&mut arp, &mut outputs, 0, 1));
+    assert!(set_parameter(&mut arp, &mut outputs, 1, 2));
+    assert!(set_parameter(&mut arp, &mut outputs, 2, 0));
+    assert!(set_parameter(&mut arp, &mut outputs, 3, 25));
+    assert!(set_parameter(&mut arp, &mut outputs, 4, 75));
+    assert!(!set_parameter(&mut arp, &mut outputs, 4, 75));
+    assert!(!set_parameter(&mut arp, &mut outputs, 2, 6));
+
+    arp.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    arp.handle_midi_event(note_on(0, 64, 100), &mut outputs, &settings);
+    let (mut notes, mut open_time) = (Vec::new(), 0);
+    for _ in 0..1600 {
+        let was_open = outputs.gate(Gate::G1);
+        arp.update(1u32.millis(), &mut outputs);
+        if outputs.gate(Gate::G1) {
+            open_time += 1;
+            if !was_open {
+                notes.push(outputs.voltage(Cv::Cv1));
+            }
+        }
+    }
+    assert_eq!(notes, [volts(76), volts(72), volts(64), volts(60)]);
+    assert_eq!(open_time, 4 * 100);
+}
+
+#[test]
+fn arp_gate_closes_before_the_next_step() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut arp = Arp::default();
+
+    assert!(set_parameter(&mut arp, &mut outputs, 3, 127));
+    arp.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    // 8 steps of 125 ms in a second, at 120 BPM
+    assert_eq!(arp_steps(&mut arp, &mut outputs), 8);
+}
+
+#[test]
+fn arp_measures_the_clock_before_following_it() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut arp = Arp::default();
+
+    // ticks too far apart to measure, the step plays with the internal tempo
+    arp.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    for _ in 0..6 {
+        arp.update(300u32.millis(), &mut outputs);
+        arp.handle_midi_event(Midi::TimingClock, &mut outputs, &settings);
+    }
+    arp.update(60u32.millis(), &mut outputs);
+    assert!(outputs.gate(Gate::G1));
+    arp.update(5u32.millis(), &mut outputs);
+    assert!(!outputs.gate(Gate::G1));
+}
+
+#[test]
+fn arp_pedals_hold_the_pattern() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut arp = Arp::default();
+
+    arp.handle_midi_event(cc(0, 64, 127), &mut outputs, &settings);
+    arp.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    arp.handle_midi_event(note_off(0, 60), &mut outputs, &settings);
+    assert!(arp_steps(&mut arp, &mut outputs) > 0);
+    arp.handle_midi_event(cc(0, 64, 0), &mut outputs, &settings);
+    assert_eq!(arp_steps(&mut arp, &mut outputs), 0);
+
+    arp.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    arp.handle_midi_event(cc(0, 66, 127), &mut outputs, &settings);
+    arp.handle_midi_event(note_off(0, 60), &mut outputs, &settings);
+    assert!(arp_steps(&mut arp, &mut outputs) > 0);
+    arp.handle_midi_event(cc(0, 66, 0), &mut outputs, &settings);
+    assert_eq!(arp_steps(&mut arp, &mut outputs), 0);
+}
+
+#[test]
+fn envelope_drives_the_selected_cv() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut mono = Mono::default();
+
+    // off by default, and only CV 2 to 4 can be selected
+    assert!(!set_parameter(&mut mono, &mut outputs, 0, 0));
+    assert!(!set_parameter(&mut mono, &mut outputs, 0, 4));
+    assert!(set_parameter(&mut mono, &mut outputs, 0, 2));
+    mono.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    mono.update(1u32.millis(), &mut outputs);
+    assert!(outputs.voltage(Cv::Cv3) > 0);
+
+    // the envelope leaves the CV at rest
+    assert!(set_parameter(&mut mono, &mut outputs, 0, 0));
+    assert_eq!(outputs.voltage(Cv::Cv3), 0);
+
+    let mut duo = Duo::default();
+    assert!(set_parameter(&mut duo, &mut outputs, 0, 1));
+    duo.handle_midi_event(note_on(0, 60, 100), &mut outputs, &settings);
+    duo.update(1u32.millis(), &mut outputs);
+    assert!(outputs.voltage(Cv::Cv3) > 0);
+    assert_eq!(outputs.voltage(Cv::Cv4), 0);
+
+    let mut split = Split::default();
+    assert!(set_parameter(&mut split, &mut outputs, 4, 1));
+    split.handle_midi_event(note_on(0, 72, 100), &mut outputs, &settings);
+    split.update(1u32.millis(), &mut outputs);
+    assert!(outputs.voltage(Cv::Cv4) > 0);
+}
+
+#[test]
+fn envelope_velocity_is_a_setting() {
+    let mut settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut mono = Mono::default();
+    assert!(set_parameter(&mut mono, &mut outputs, 0, 2));
+
+    assert!(set_setting(&mut settings, 2, 1));
+    assert!(!set_setting(&mut settings, 2, 2));
+    mono.handle_midi_event(note_on(0, 60, 127), &mut outputs, &settings);
+    mono.update(1u32.millis(), &mut outputs);
+    let loud = outputs.voltage(Cv::Cv3);
+    mono.handle_midi_event(note_off(0, 60), &mut outputs, &settings);
+    mono.update(1u32.secs(), &mut outputs);
+    mono.handle_midi_event(note_on(0, 60, 32), &mut outputs, &settings);
+    mono.update(1u32.millis(), &mut outputs);
+    assert!(outputs.voltage(Cv::Cv3) > 0 && outputs.voltage(Cv::Cv3) < loud);
+}
+
+#[test]
+fn pressure_drives_the_selected_cv() {
+    let settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let pressure = |value: u8| Midi::ChannelPressure(0.into(), value.into());
+
+    let mut mono = Mono::default();
+    mono.handle_midi_event(pressure(127), &mut outputs, &settings);
+    assert!(outputs.take_events().is_empty());
+    assert!(set_parameter(&mut mono, &mut outputs, 1, 3));
+    mono.handle_midi_event(pressure(127), &mut outputs, &settings);
+    assert_eq!(outputs.voltage(Cv::Cv4), U16F16::from_num(8));
+    assert!(set_parameter(&mut mono, &mut outputs, 1, 0));
+    assert_eq!(outputs.voltage(Cv::Cv4), 0);
+
+    let mut duo = Duo::default();
+    assert!(set_parameter(&mut duo, &mut outputs, 0, 2));
+    duo.handle_midi_event(pressure(127), &mut outputs, &settings);
+    assert_eq!(outputs.voltage(Cv::Cv3), U16F16::from_num(8));
+    assert_eq!(outputs.voltage(Cv::Cv4), U16F16::from_num(8));
+}
+
+#[test]
+fn omni_follows_the_basic_channel() {
+    let mut settings = Settings::default();
+    let mut outputs = MockOutputs::default();
+    let mut mono = Mono::default();
+
+    handle_channel_mode(cc(3, 125, 0), &mut mono, &mut outputs, &mut settings);
+    assert!(!settings.omni);
+    handle_channel_mode(cc(0, 125, 0), &mut mono, &mut outputs, &mut settings);
+    assert!(settings.omni);
+
+    mono.handle_midi_event(note_on(3, 60, 100), &mut outputs, &settings);
+    handle_channel_mode(cc(3, 124, 0), &mut mono, &mut outputs, &mut settings);
+    assert!(settings.omni);
+    assert!(outputs.gate(Gate::G1));
+    handle_channel_mode(cc(0, 124, 0), &mut mono, &mut outputs, &mut settings);
+    assert!(!settings.omni);
+    assert!(!outputs.gate(Gate::G1));
+}
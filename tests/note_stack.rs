@@ -0,0 +1,306 @@
+#![allow(clippy::needless_return)]
+
+extern crate etas_midi2cv_firmware;
+
+use etas_midi2cv_firmware::note_stack::{
+    Change, Config, NotePriority, NoteStack, Retrigger, MAX_NOTES,
+};
+
+const PRIORITIES: [NotePriority; 4] =
+    [NotePriority::Latest, NotePriority::First, NotePriority::Highest, NotePriority::Lowest];
+
+fn config(priority: NotePriority) -> Config {
+    return Config { priority, retrigger: Retrigger::Ignore, legato: false };
+}
+
+fn change(gate: Option<bool>, note: Option<u8>, retrigger: bool) -> Change {
+    return Change { gate, note, retrigger };
+}
+
+/// The note a voice should play for the held notes, in the order they were played.
+fn expected(held: &[u8], priority: NotePriority) -> Option<u8> {
+    return match priority {
+        NotePriority::Latest => held.last().copied(),
+        NotePriority::First => held.first().copied(),
+        NotePriority::Highest => held.iter().max().copied(),
+        NotePriority::Lowest => held.iter().min().copied(),
+    };
+}
+
+fn permutations(notes: &[u8]) -> Vec<Vec<u8>> {
+    if notes.len() <= 1 {
+        return vec![notes.to_vec()];
+    }
+    let mut result = Vec::new();
+    for i in 0..notes.len() {
+        let mut rest = notes.to_vec();
+        let note = rest.remove(i);
+        for mut permutation in permutations(&rest) {
+            permutation.insert(0, note);
+            result.push(permutation);
+        }
+    }
+    return result;
+}
+
+/// Checks a change against the active note before and after an operation.
+fn check(change: Change, before: Option<u8>, after: Option<u8>, legato: bool) {
+    let gate = match (before, after) {
+        (None, Some(_)) => Some(true),
+        (Some(_), None) => Some(false),
+        _ => None,
+    };
+    assert_eq!(change.gate, gate);
+    let is_pitch_changed = after.is_some() && before != after;
+    assert_eq!(change.note, if is_pitch_changed { after } else { None });
+    let retrigger = gate == Some(true) || (is_pitch_changed && !legato);
+    assert_eq!(change.retrigger, retrigger);
+}
+
+#[test]
+fn all_press_and_release_orders() {
+    let notes = [60, 64, 55, 67];
+    for &priority in &PRIORITIES {
+        for &legato in &[false, true] {
+            let config = Config { legato, ..config(priority) };
+            for presses in permutations(&notes) {
+                for releases in permutations(&notes) {
+                    let mut stack = NoteStack::new(8);
+                    let mut held = Vec::new();
+                    for &note in &presses {
+                        let before = expected(&held, priority);
+                        held.push(note);
+                        let change = stack.note_on(note, &config);
+                        check(change, before, expected(&held, priority), legato);
+                        assert_eq!(stack.notes(), &held[..]);
+                        assert_eq!(stack.active_note(), expected(&held, priority));
+                    }
+                    for &note in &releases {
+                        let before = expected(&held, priority);
+                        held.retain(|&n| n != note);
+                        let change = stack.note_off(note, &config);
+                        check(change, before, expected(&held, priority), legato);
+                        assert_eq!(stack.notes(), &held[..]);
+                        assert_eq!(stack.active_note(), expected(&held, priority));
+                    }
+                    assert!(stack.is_empty());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn interleaved_presses_and_releases() {
+    // every sequence of six events on three keys, pressing released keys and releasing pressed
+    for &priority in &PRIORITIES {
+        let config = config(priority);
+        for sequence in 0..3usize.pow(6) {
+            let mut stack = NoteStack::new(8);
+            let mut held: Vec<u8> = Vec::new();
+            let mut rest = sequence;
+            for _ in 0..6 {
+                let note = [48, 52, 50][rest % 3];
+                rest /= 3;
+                let before = expected(&held, priority);
+                let change = if held.contains(&note) {
+                    held.retain(|&n| n != note);
+                    stack.note_off(note, &config)
+                }
+                else {
+                    held.push(note);
+                    stack.note_on(note, &config)
+                };
+                check(change, before, expected(&held, priority), false);
+                assert_eq!(stack.notes(), &held[..]);
+                assert_eq!(stack.active_note(), expected(&held, priority));
+            }
+        }
+    }
+}
+
+#[test]
+fn release_of_unknown_note_is_ignored() {
+    let config = config(NotePriority::Latest);
+    let mut stack = NoteStack::new(8);
+    assert_eq!(stack.note_off(60, &config), Change::default());
+    stack.note_on(60, &config);
+    assert_eq!(stack.note_off(61, &config), Change::default());
+    assert_eq!(stack.notes(), &[60]);
+}
+
+#[test]
+fn overflow_drops_lowest_priority_note() {
+    let notes = [60, 72, 48, 65];
+    let cases = [
+        (NotePriority::Latest, [72, 48, 65, 50], Some(50)),
+        (NotePriority::First, [60, 72, 48, 50], Some(60)),
+        (NotePriority::Highest, [60, 72, 65, 50], Some(72)),
+        (NotePriority::Lowest, [60, 48, 65, 50], Some(48)),
+    ];
+    for &(priority, remaining, active) in &cases {
+        let config = config(priority);
+        let mut stack = NoteStack::new(4);
+        for &note in &notes {
+            stack.note_on(note, &config);
+        }
+        stack.note_on(50, &config);
+        assert_eq!(stack.notes(), &remaining, "{:?}", priority);
+        assert_eq!(stack.active_note(), active, "{:?}", priority);
+        assert_eq!(stack.dropped(), 1);
+    }
+}
+
+#[test]
+fn newest_note_sounds_when_full() {
+    let config = config(NotePriority::Latest);
+    let mut stack = NoteStack::new(2);
+    stack.note_on(59, &config);
+    for note in 60..70 {
+        assert_eq!(stack.note_on(note, &config), change(None, Some(note), true));
+        assert_eq!(stack.active_note(), Some(note));
+    }
+    assert_eq!(stack.dropped(), 9);
+}
+
+#[test]
+fn depth_is_clamped() {
+    let config = config(NotePriority::Latest);
+    let mut stack = NoteStack::new(0);
+    assert_eq!(stack.note_on(60, &config), change(Some(true), Some(60), true));
+    assert_eq!(stack.note_on(62, &config), change(None, Some(62), true));
+    assert_eq!(stack.notes(), &[62]);
+
+    let mut stack = NoteStack::new(MAX_NOTES + 1);
+    for note in 0..=MAX_NOTES as u8 {
+        stack.note_on(note, &config);
+    }
+    assert_eq!(stack.len(), MAX_NOTES);
+}
+
+#[test]
+fn repeated_note() {
+    let policies = [
+        (Retrigger::Ignore, Change::default()),
+        (Retrigger::Trigger, change(None, None, true)),
+        (Retrigger::Reopen, change(None, None, true)),
+        (Retrigger::Restack, change(None, Some(60), true)),
+    ];
+    for &(retrigger, expected) in &policies {
+        let config = Config { retrigger, ..config(NotePriority::Latest) };
+        let mut stack = NoteStack::new(8);
+        stack.note_on(60, &config);
+        stack.note_on(64, &config);
+        assert_eq!(stack.note_on(60, &config), expected, "{:?}", retrigger);
+    }
+}
+
+#[test]
+fn restack_moves_note_to_top() {
+    let config = Config { retrigger: Retrigger::Restack, ..config(NotePriority::Latest) };
+    let mut stack = NoteStack::new(8);
+    for &note in &[60, 64, 67] {
+        stack.note_on(note, &config);
+    }
+    stack.note_on(60, &config);
+    assert_eq!(stack.notes(), &[64, 67, 60]);
+    assert_eq!(stack.note_on(60, &config), Change::default());
+    assert_eq!(stack.note_off(60, &config), change(None, Some(67), true));
+}
+
+#[test]
+fn restack_only_applies_to_latest() {
+    let config = Config { retrigger: Retrigger::Restack, ..config(NotePriority::Highest) };
+    let mut stack = NoteStack::new(8);
+    stack.note_on(60, &config);
+    stack.note_on(64, &config);
+    assert_eq!(stack.note_on(60, &config), Change::default());
+    assert_eq!(stack.notes(), &[60, 64]);
+}
+
+#[test]
+fn sustain_holds_released_notes() {
+    let config = config(NotePriority::Latest);
+    let mut stack = NoteStack::new(8);
+    stack.note_on(60, &config);
+    stack.note_on(64, &config);
+    assert_eq!(stack.set_sustain(true, &config), Change::default());
+    assert_eq!(stack.note_off(64, &config), Change::default());
+    assert_eq!(stack.note_off(60, &config), Change::default());
+    assert_eq!(stack.active_note(), Some(64));
+    stack.note_on(67, &config);
+    assert_eq!(stack.set_sustain(false, &config), change(None, None, false));
+    assert_eq!(stack.notes(), &[67]);
+    assert_eq!(stack.note_off(67, &config), change(Some(false), None, false));
+}
+
+#[test]
+fn sustain_release_closes_gate() {
+    let config = config(NotePriority::Latest);
+    let mut stack = NoteStack::new(8);
+    stack.note_on(60, &config);
+    stack.note_on(64, &config);
+    stack.set_sustain(true, &config);
+    stack.note_off(60, &config);
+    stack.note_off(64, &config);
+    assert_eq!(stack.set_sustain(false, &config), change(Some(false), None, false));
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn sustain_release_falls_back_to_held_key() {
+    let config = config(NotePriority::Latest);
+    let mut stack = NoteStack::new(8);
+    stack.note_on(60, &config);
+    stack.set_sustain(true, &config);
+    stack.note_on(64, &config);
+    stack.note_off(64, &config);
+    assert_eq!(stack.set_sustain(false, &config), change(None, Some(60), true));
+    assert_eq!(stack.notes(), &[60]);
+}
+
+#[test]
+fn sostenuto_holds_only_notes_down_when_pressed() {
+    let config = config(NotePriority::Latest);
+    let mut stack = NoteStack::new(8);
+    stack.note_on(48, &config);
+    stack.set_sostenuto(true, &config);
+    stack.note_on(60, &config);
+    assert_eq!(stack.note_off(60, &config), change(None, Some(48), true));
+    assert_eq!(stack.note_off(48, &config), Change::default());
+    assert_eq!(stack.notes(), &[48]);
+
+    // pressing the pedal again while held does not capture new keys
+    stack.note_on(62, &config);
+    stack.set_sostenuto(true, &config);
+    assert_eq!(stack.note_off(62, &config), change(None, Some(48), true));
+    assert_eq!(stack.set_sostenuto(false, &config), change(Some(false), None, false));
+}
+
+#[test]
+fn legato_suppresses_retrigger() {
+    let config = Config { legato: true, ..config(NotePriority::Latest) };
+    let mut stack = NoteStack::new(8);
+    assert_eq!(stack.note_on(60, &config), change(Some(true), Some(60), true));
+    assert_eq!(stack.note_on(64, &config), change(None, Some(64), false));
+    assert_eq!(stack.note_off(64, &config), change(None, Some(60), false));
+    assert_eq!(stack.note_off(60, &config), change(Some(false), None, false));
+}
+
+#[test]
+fn clear_and_reset() {
+    let config = config(NotePriority::Latest);
+    let mut stack = NoteStack::new(8);
+    stack.note_on(60, &config);
+    stack.set_sustain(true, &config);
+    stack.clear();
+    assert!(stack.is_empty());
+    assert_eq!(stack.active_note(), None);
+
+    // the pedal is still down after clearing, but not after a reset
+    stack.note_on(60, &config);
+    assert_eq!(stack.note_off(60, &config), Change::default());
+    stack.reset();
+    stack.note_on(60, &config);
+    assert_eq!(stack.note_off(60, &config), change(Some(false), None, false));
+}
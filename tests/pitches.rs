@@ -0,0 +1,96 @@
+#![allow(clippy::needless_return)]
+
+extern crate etas_midi2cv_firmware;
+extern crate fixed;
+
+use etas_midi2cv_firmware::io::{Correction, Cv, PitchScale, PitchStandard, Pitches, ROOT_NOTE};
+
+use fixed::types::{I16F16, U16F16};
+
+fn note(note: f32) -> I16F16 {
+    return I16F16::from_num(note + ROOT_NOTE as f32);
+}
+
+fn assert_volts(voltage: U16F16, expected: f32) {
+    let delta = (voltage.to_num::<f32>() - expected).abs();
+    assert!(delta < 0.0001, "{} != {}", voltage, expected);
+}
+
+#[test]
+fn uncorrected_pitches_are_one_volt_per_octave() {
+    let mut pitches = Pitches::default();
+    assert_volts(pitches.set_note(Cv::Cv1, note(0.0)), 0.0);
+    assert_volts(pitches.set_note(Cv::Cv1, note(12.0)), 1.0);
+    assert_volts(pitches.set_note(Cv::Cv1, note(31.0)), 31.0 / 12.0);
+    assert_volts(pitches.set_note(Cv::Cv1, note(-5.0)), 0.0);
+}
+
+#[test]
+fn fine_tune_shifts_one_channel() {
+    let mut pitches = Pitches::default();
+    pitches.correction_mut().set_fine_tune(Cv::Cv2, -50);
+    assert_volts(pitches.set_note(Cv::Cv1, note(12.0)), 1.0);
+    assert_volts(pitches.set_note(Cv::Cv2, note(12.0)), 11.5 / 12.0);
+    // can't go below zero volts
+    assert_volts(pitches.set_note(Cv::Cv2, note(0.0)), 0.0);
+}
+
+#[test]
+fn semitone_corrections_are_interpolated() {
+    let mut pitches = Pitches::default();
+    pitches.correction_mut().set_semitone(Cv::Cv1, 6, 10);
+    pitches.correction_mut().set_semitone(Cv::Cv1, 7, 30);
+    assert_volts(pitches.set_note(Cv::Cv1, note(5.0)), 5.0 / 12.0);
+    assert_volts(pitches.set_note(Cv::Cv1, note(6.0)), 6.1 / 12.0);
+    assert_volts(pitches.set_note(Cv::Cv1, note(6.5)), 6.7 / 12.0);
+    pitches.set_note(Cv::Cv1, note(6.0));
+    assert_volts(pitches.set_bend(Cv::Cv1, I16F16::from_num(1)), 7.3 / 12.0);
+    // the top of the range uses the last correction
+    pitches.correction_mut().set_semitone(Cv::Cv1, Correction::N_SEMITONES - 1, 100);
+    assert_volts(pitches.set_bend(Cv::Cv1, I16F16::ZERO), 6.1 / 12.0);
+    assert_volts(pitches.set_note(Cv::Cv1, note(96.0)), 97.0 / 12.0);
+}
+
+fn scale(standard: PitchStandard) -> PitchScale {
+    return PitchScale { standard, ..PitchScale::default() };
+}
+
+#[test]
+fn root_note_is_at_zero_volts() {
+    let mut pitches = Pitches::default();
+    pitches.set_scale(Cv::Cv1, PitchScale { root: 36, ..PitchScale::default() });
+    assert_volts(pitches.set_note(Cv::Cv1, I16F16::from_num(36)), 0.0);
+    assert_volts(pitches.set_note(Cv::Cv1, I16F16::from_num(55)), 19.0 / 12.0);
+}
+
+#[test]
+fn linear_standards() {
+    let mut pitches = Pitches::default();
+    pitches.set_scale(Cv::Cv1, scale(PitchStandard::Buchla));
+    assert_volts(pitches.set_note(Cv::Cv1, note(12.0)), 1.2);
+    assert_volts(pitches.set_note(Cv::Cv1, note(17.0)), 1.7);
+
+    let custom = PitchScale { millivolts: 1035, ..scale(PitchStandard::Custom) };
+    pitches.set_scale(Cv::Cv2, custom);
+    assert_volts(pitches.set_note(Cv::Cv2, note(24.0)), 2.07);
+    assert_volts(pitches.set_note(Cv::Cv2, note(6.0)), 0.5175);
+}
+
+#[test]
+fn hz_per_volt_doubles_the_voltage_every_octave() {
+    let mut pitches = Pitches::default();
+    pitches.set_scale(Cv::Cv1, scale(PitchStandard::HzPerVolt));
+    // A1 is 1V
+    assert_volts(pitches.set_note(Cv::Cv1, I16F16::from_num(33)), 1.0);
+    assert_volts(pitches.set_note(Cv::Cv1, I16F16::from_num(45)), 2.0);
+    assert_volts(pitches.set_note(Cv::Cv1, I16F16::from_num(69)), 8.0);
+    assert_volts(pitches.set_note(Cv::Cv1, I16F16::from_num(28)), 0.5f32.powf(5.0 / 12.0));
+    for cents in 0..=100 {
+        let pitch = 40.0 + cents as f32 / 100.0;
+        let expected = 2f32.powf((pitch - 33.0) / 12.0);
+        let voltage = pitches.set_note(Cv::Cv1, I16F16::from_num(pitch)).to_num::<f32>();
+        // within a tenth of a cent
+        let error = 1200.0 * (voltage / expected).log2();
+        assert!(error.abs() < 0.1, "{} cents off at {}", error, pitch);
+    }
+}
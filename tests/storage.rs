@@ -0,0 +1,234 @@
+#![allow(clippy::needless_return)]
+
+extern crate embedded_midi;
+extern crate etas_midi2cv_firmware;
+
+use etas_midi2cv_firmware::context::{Context, Menu};
+use etas_midi2cv_firmware::io::{Gate, Pitches};
+use etas_midi2cv_firmware::mock::{MockFlash, MockOutputs};
+use etas_midi2cv_firmware::modes::*;
+use etas_midi2cv_firmware::settings::{NotePriority, Settings, TriggerLength};
+use etas_midi2cv_firmware::storage::{
+    Error, Reader, Storage, Writer, MAX_DATA, N_PAGES, PAGE_SIZE,
+};
+
+use embedded_midi::MidiMessage as Midi;
+
+fn load(flash: &mut MockFlash) -> Option<Vec<u8>> {
+    let mut data = [0; MAX_DATA];
+    let (_, len) = Storage::open(flash, 0..N_PAGES, &mut data).unwrap();
+    return len.map(|len| data[..len].to_vec());
+}
+
+fn record(i: usize, len: usize) -> Vec<u8> {
+    return (0..len).map(|j| (i * 7 + j) as u8).collect();
+}
+
+#[test]
+fn empty_flash_has_no_data() {
+    let mut flash = MockFlash::default();
+    assert_eq!(load(&mut flash), None);
+}
+
+#[test]
+fn latest_record_is_restored() {
+    let mut flash = MockFlash::default();
+    let mut data = [0; MAX_DATA];
+    let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+    storage.save(&mut flash, &record(0, 5)).unwrap();
+    assert_eq!(load(&mut flash), Some(record(0, 5)));
+    storage.save(&mut flash, &record(1, 6)).unwrap();
+    assert_eq!(load(&mut flash), Some(record(1, 6)));
+}
+
+#[test]
+fn saves_are_spread_over_all_pages() {
+    let mut flash = MockFlash::default();
+    for i in 0..1000 {
+        // reopen every time, as after a power cycle
+        let mut data = [0; MAX_DATA];
+        let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+        storage.save(&mut flash, &record(i, 100 + i % 3)).unwrap();
+        assert_eq!(load(&mut flash), Some(record(i, 100 + i % 3)));
+    }
+    // 1000 records of about 110 bytes, 9 per page
+    let erases = flash.erases;
+    assert!(erases.iter().all(|&n| (27..=29).contains(&n)), "{:?}", erases);
+}
+
+#[test]
+fn corrupt_record_falls_back_to_previous() {
+    let mut flash = MockFlash::default();
+    let mut data = [0; MAX_DATA];
+    let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+    storage.save(&mut flash, &record(0, 10)).unwrap();
+    storage.save(&mut flash, &record(1, 10)).unwrap();
+    flash.data[20 + 10] ^= 0x01;
+    assert_eq!(load(&mut flash), Some(record(0, 10)));
+}
+
+#[test]
+fn interrupted_save_is_ignored_and_skipped() {
+    let mut flash = MockFlash::default();
+    let mut data = [0; MAX_DATA];
+    let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+    storage.save(&mut flash, &record(0, 10)).unwrap();
+    storage.save(&mut flash, &record(1, 10)).unwrap();
+    // power was lost before the CRC was written
+    flash.data[38..40].fill(0xff);
+    assert_eq!(load(&mut flash), Some(record(0, 10)));
+
+    let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+    storage.save(&mut flash, &record(2, 10)).unwrap();
+    assert_eq!(load(&mut flash), Some(record(2, 10)));
+}
+
+#[test]
+fn other_versions_are_ignored() {
+    let mut flash = MockFlash::default();
+    let mut data = [0; MAX_DATA];
+    let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+    storage.save(&mut flash, &record(0, 10)).unwrap();
+    flash.data[0] = 0;
+    assert_eq!(load(&mut flash), None);
+}
+
+#[test]
+fn oversized_data_is_rejected() {
+    let mut flash = MockFlash::default();
+    let mut data = [0; MAX_DATA];
+    let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+    assert_eq!(storage.save(&mut flash, &[0; MAX_DATA + 1]), Err(Error::TooLarge));
+    storage.save(&mut flash, &record(0, MAX_DATA)).unwrap();
+    storage.save(&mut flash, &record(1, MAX_DATA)).unwrap();
+    assert_eq!(load(&mut flash), Some(record(1, MAX_DATA)));
+    assert_eq!(flash.erases, [1, 1, 0, 0]);
+    assert_eq!(flash.data.len(), N_PAGES * PAGE_SIZE);
+}
+
+#[test]
+fn unchanged_data_is_not_saved_again() {
+    let mut flash = MockFlash::default();
+    let mut data = [0; MAX_DATA];
+    let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+    storage.save(&mut flash, &record(0, 300)).unwrap();
+    let (mut storage, _) = Storage::open(&mut flash, 0..N_PAGES, &mut data).unwrap();
+    for _ in 0..10 {
+        storage.save(&mut flash, &record(0, 300)).unwrap();
+    }
+    assert_eq!(flash.data[PAGE_SIZE - 1], 0xff);
+    storage.save(&mut flash, &record(1, 300)).unwrap();
+    assert_eq!(load(&mut flash), Some(record(1, 300)));
+    assert_eq!(flash.erases, [1, 0, 0, 0]);
+}
+
+#[test]
+fn logs_keep_to_their_pages() {
+    let mut flash = MockFlash::default();
+    let mut data = [0; MAX_DATA];
+    let (mut first, _) = Storage::open(&mut flash, 0..2, &mut data).unwrap();
+    let (mut second, _) = Storage::open(&mut flash, 2..4, &mut data).unwrap();
+    for i in 0..20 {
+        first.save(&mut flash, &record(i, 200)).unwrap();
+    }
+    second.save(&mut flash, &record(100, 900)).unwrap();
+    assert_eq!(flash.erases, [3, 2, 1, 0]);
+
+    let (_, len) = Storage::open(&mut flash, 0..2, &mut data).unwrap();
+    assert_eq!(data[..len.unwrap()], record(19, 200)[..]);
+    let (_, len) = Storage::open(&mut flash, 2..4, &mut data).unwrap();
+    assert_eq!(data[..len.unwrap()], record(100, 900)[..]);
+}
+
+#[test]
+fn overflowing_writer_is_an_error() {
+    let mut writer = Writer::default();
+    for _ in 0..MAX_DATA {
+        writer.u8(1);
+    }
+    assert_eq!(writer.as_bytes().map(|bytes| bytes.len()), Ok(MAX_DATA));
+    writer.u16(2);
+    assert_eq!(writer.as_bytes(), Err(Error::TooLarge));
+}
+
+#[test]
+fn state_record_fits_several_times_into_a_page() {
+    let mut writer = Writer::default();
+    writer.i8(0);
+    Settings::default().save(&mut writer);
+    let modes: [&dyn Mode<MockOutputs>; 7] = [
+        &Mono::default(),
+        &Poly::default(),
+        &Duo::default(),
+        &Drum::default(),
+        &Multi::default(),
+        &Split::default(),
+        &Arp::default(),
+    ];
+    for mode in modes {
+        mode.save(&mut writer);
+    }
+    Pitches::default().save(&mut writer);
+    // and the DAC calibration, 9 levels of 4 channels
+    let len = writer.as_bytes().unwrap().len() + 9 * 4 * 2;
+    assert!(len <= MAX_DATA / 4, "{}", len);
+
+    let mut writer = Writer::default();
+    Pitches::default().save_tables(&mut writer);
+    assert!(writer.as_bytes().is_ok());
+}
+
+#[test]
+fn settings_round_trip() {
+    let settings = Settings {
+        note_priority: NotePriority::Lowest,
+        trigger_length: TriggerLength::T25ms,
+        legato: true,
+        ..Settings::default()
+    };
+    let mut writer = Writer::default();
+    settings.save(&mut writer);
+    let loaded = Settings::load(&mut Reader::new(writer.as_bytes().unwrap())).unwrap();
+    assert_eq!(loaded.note_priority, NotePriority::Lowest);
+    assert!(matches!(loaded.trigger_length, TriggerLength::T25ms));
+    assert!(loaded.legato);
+
+    let bytes = writer.as_bytes().unwrap();
+    assert!(Settings::load(&mut Reader::new(&bytes[..bytes.len() - 1])).is_none());
+}
+
+#[test]
+fn learned_channel_is_restored() {
+    let settings = Settings::default();
+    let context = Context::new(Menu::MidiLearn);
+    let mut outputs = MockOutputs::default();
+    let note_on = Midi::NoteOn(5.into(), 60.into(), 100.into());
+    let mut mono = Mono::default();
+    mono.handle_midi_learn(note_on, &mut outputs, &context);
+
+    let mut writer = Writer::default();
+    Mode::<MockOutputs>::save(&mono, &mut writer);
+    let mut mono = Mono::default();
+    let bytes = writer.as_bytes().unwrap();
+    Mode::<MockOutputs>::load(&mut mono, &mut Reader::new(bytes)).unwrap();
+
+    mono.handle_midi_event(note_on, &mut outputs, &settings);
+    assert!(outputs.gate(Gate::G1));
+}
+
+#[test]
+fn invalid_arp_settings_are_rejected() {
+    let mut writer = Writer::default();
+    Mode::<MockOutputs>::save(&Arp::default(), &mut writer);
+    let bytes = writer.as_bytes().unwrap();
+    let mut arp = Arp::default();
+    assert!(Mode::<MockOutputs>::load(&mut arp, &mut Reader::new(bytes)).is_some());
+
+    // octaves, gate length and tempo
+    for (index, value) in [(2, 0), (2, 5), (4, 0), (4, 100), (5, 0), (5, 241)] {
+        let mut bytes = bytes.to_vec();
+        bytes[index] = value;
+        let reader = &mut Reader::new(&bytes);
+        assert!(Mode::<MockOutputs>::load(&mut arp, reader).is_none(), "{}", index);
+    }
+}
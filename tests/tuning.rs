@@ -0,0 +1,212 @@
+#![allow(clippy::needless_return)]
+
+extern crate embedded_midi;
+extern crate etas_midi2cv_firmware;
+extern crate fixed;
+
+use etas_midi2cv_firmware::io::{Cv, Pitches, ROOT_NOTE};
+use etas_midi2cv_firmware::storage::{Reader, Writer};
+use etas_midi2cv_firmware::tuning::{
+    MtsReceiver, Selector, Table, TableUpdate, Temperament, Tuning, NRPN_MSB,
+};
+
+use embedded_midi::MidiMessage as Midi;
+use fixed::types::I16F16;
+
+fn pitch(tuning: &Tuning, note: u8) -> f32 {
+    return tuning.pitch(I16F16::from_num(note), &Table::default()).to_num();
+}
+
+fn assert_pitch(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 0.0002, "{} != {}", actual, expected);
+}
+
+fn tuning(temperament: Temperament, tonic: u8) -> Tuning {
+    return Tuning { temperament, tonic, ..Tuning::default() };
+}
+
+/// An MTS bulk dump of `table`, tuning every note to a pitch given in semitones.
+fn bulk_dump(table: impl Fn(u8) -> f32) -> Vec<u8> {
+    let mut data = vec![0x7e, 0x7f, 0x08, 0x01, 0x00];
+    data.extend(b"test tuning     ");
+    for note in 0..128 {
+        let pitch = table(note);
+        let fraction = ((pitch - pitch.floor()) * 16384.0).round() as u16;
+        data.extend([pitch.floor() as u8, (fraction >> 7) as u8, (fraction & 0x7f) as u8]);
+    }
+    data.push(data.iter().fold(0, |checksum, byte| checksum ^ byte));
+    return [vec![0xf0], data, vec![0xf7]].concat();
+}
+
+fn receive(receiver: &mut MtsReceiver, table: &mut Table, bytes: &[u8]) -> Vec<TableUpdate> {
+    return bytes.iter().filter_map(|&byte| receiver.parse_byte(byte, table)).collect();
+}
+
+#[test]
+fn equal_temperament_keeps_notes() {
+    let tuning = Tuning::default();
+    for note in 0..128 {
+        assert_pitch(pitch(&tuning, note), note as f32);
+    }
+}
+
+#[test]
+fn temperaments_are_built_on_the_tonic() {
+    // a just major third above C
+    let just = tuning(Temperament::Just, 0);
+    assert_pitch(pitch(&just, 60), 60.0);
+    assert_pitch(pitch(&just, 64), 60.0 + 12.0 * (5.0f32 / 4.0).log2());
+    assert_pitch(pitch(&just, 79), 72.0 + 12.0 * (3.0f32 / 2.0).log2());
+
+    // a Pythagorean major third above D
+    let pythagorean = tuning(Temperament::Pythagorean, 2);
+    assert_pitch(pitch(&pythagorean, 62), 62.0);
+    assert_pitch(pitch(&pythagorean, 66), 62.0 + 12.0 * (81.0f32 / 64.0).log2());
+
+    // meantone fifths are a quarter comma narrow
+    let meantone = tuning(Temperament::Meantone, 0);
+    assert_pitch(pitch(&meantone, 67), 67.0 - 0.03422);
+    assert_pitch(pitch(&meantone, 64) - pitch(&meantone, 60), 4.0 - 0.13686);
+}
+
+#[test]
+fn edo_maps_one_step_per_key() {
+    let edo = Tuning { edo: 19, ..tuning(Temperament::Edo, 0) };
+    assert_pitch(pitch(&edo, 60), 60.0);
+    assert_pitch(pitch(&edo, 61), 60.0 + 12.0 / 19.0);
+    assert_pitch(pitch(&edo, 79), 72.0);
+    assert_pitch(pitch(&edo, 41), 48.0);
+
+    // the tonic above middle C stays in place
+    let edo = Tuning { edo: 5, ..tuning(Temperament::Edo, 9) };
+    assert_pitch(pitch(&edo, 69), 69.0);
+    assert_pitch(pitch(&edo, 74), 81.0);
+}
+
+#[test]
+fn gliding_notes_are_interpolated() {
+    let just = tuning(Temperament::Just, 0);
+    let table = Table::default();
+    let (x0, x1) = (pitch(&just, 63), pitch(&just, 64));
+    let between = just.pitch(I16F16::from_num(63.25), &table).to_num::<f32>();
+    assert_pitch(between, x0 + (x1 - x0) * 0.25);
+}
+
+#[test]
+fn selected_tuning_drives_the_pitch_cv() {
+    let mut pitches = Pitches::default();
+    pitches.set_tuning(Tuning { edo: 24, ..tuning(Temperament::Edo, 0) });
+    let voltage = pitches.set_note(Cv::Cv1, I16F16::from_num(61)).to_num::<f32>();
+    assert_pitch(voltage, (60.5 - ROOT_NOTE as f32) / 12.0);
+}
+
+#[test]
+fn selector_changes_the_tuning() {
+    let cc = |cc: u8, value: u8| Midi::ControlChange(3.into(), cc.into(), value.into());
+    let mut selector = Selector::default();
+    let mut tuning = Tuning::default();
+
+    let select = |selector: &mut Selector, tuning: &mut Tuning, lsb: u8, value: u8| {
+        selector.handle_midi_event(cc(99, NRPN_MSB), tuning);
+        selector.handle_midi_event(cc(98, lsb), tuning);
+        return selector.handle_midi_event(cc(6, value), tuning);
+    };
+    assert!(select(&mut selector, &mut tuning, 0, 4));
+    assert!(select(&mut selector, &mut tuning, 1, 17));
+    assert!(select(&mut selector, &mut tuning, 2, 14));
+    assert_eq!(tuning, Tuning { temperament: Temperament::Edo, tonic: 2, edo: 17 });
+
+    assert!(!select(&mut selector, &mut tuning, 1, 17));
+    assert!(select(&mut selector, &mut tuning, 1, 100));
+    assert_eq!(tuning.edo, 31);
+    assert!(!select(&mut selector, &mut tuning, 0, 6));
+    assert_eq!(tuning.temperament, Temperament::Edo);
+
+    // pitch bend sensitivity
+    selector.handle_midi_event(cc(101, 0), &mut tuning);
+    selector.handle_midi_event(cc(100, 0), &mut tuning);
+    assert!(!selector.handle_midi_event(cc(6, 0), &mut tuning));
+}
+
+#[test]
+fn bulk_dump_replaces_the_table() {
+    let mut receiver = MtsReceiver::default();
+    let mut table = Table::default();
+    let mut dump = bulk_dump(|note| note as f32 * 0.5 + 0.25);
+    // leave one note unchanged
+    dump[22 + 3 * 10..22 + 3 * 11].fill(0x7f);
+    let checksum = dump.len() - 2;
+    dump[checksum] = dump[1..checksum].iter().fold(0, |checksum, byte| checksum ^ byte);
+    // interleaved clock
+    dump.insert(100, 0xf8);
+
+    assert_eq!(receive(&mut receiver, &mut table, &dump), [TableUpdate::BulkDump]);
+    assert_eq!(table.pitch(0), I16F16::from_num(0.25));
+    assert_eq!(table.pitch(9), I16F16::from_num(4.75));
+    assert_eq!(table.pitch(10), I16F16::from_num(10));
+    assert_eq!(table.pitch(127), I16F16::from_num(63.75));
+}
+
+#[test]
+fn broken_bulk_dumps_are_ignored() {
+    let mut receiver = MtsReceiver::default();
+    let mut table = Table::default();
+    let dump = bulk_dump(|note| note as f32 + 0.5);
+
+    let mut corrupt = dump.clone();
+    corrupt[50] ^= 0x01;
+    assert_eq!(receive(&mut receiver, &mut table, &corrupt), []);
+
+    let truncated = [&dump[..200], &[0x90, 60, 100], &dump[200..]].concat();
+    assert_eq!(receive(&mut receiver, &mut table, &truncated), []);
+
+    let mut other = dump.clone();
+    other[3] = 0x09;
+    assert_eq!(receive(&mut receiver, &mut table, &other), []);
+    assert_eq!(table.pitch(60), I16F16::from_num(60));
+
+    assert_eq!(receive(&mut receiver, &mut table, &dump), [TableUpdate::BulkDump]);
+    assert_eq!(table.pitch(60), I16F16::from_num(60.5));
+}
+
+#[test]
+fn single_note_changes_apply_right_away() {
+    let mut receiver = MtsReceiver::default();
+    let mut table = Table::default();
+    // A4 to 440Hz exactly, C4 a 14 bit fraction up and D4 unchanged
+    let message = [
+        0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x03, 69, 69, 0x00, 0x00, 60, 60, 0x00, 0x01, 62,
+        0x7f, 0x7f, 0x7f, 0xf7,
+    ];
+    let updates = receive(&mut receiver, &mut table, &message);
+    assert_eq!(updates, [TableUpdate::SingleNote; 3]);
+    assert_eq!(table.pitch(69), I16F16::from_num(69));
+    assert_eq!(table.pitch(60), I16F16::from_bits((60 << 16) | 4));
+    assert_eq!(table.pitch(62), I16F16::from_num(62));
+
+    // with a bank, C4 to a quarter tone up
+    let message = [0xf0, 0x7e, 0x00, 0x08, 0x07, 0x00, 0x00, 0x01, 60, 60, 0x20, 0x00, 0xf7];
+    assert_eq!(receive(&mut receiver, &mut table, &message), [TableUpdate::SingleNote]);
+    assert_eq!(table.pitch(60), I16F16::from_num(60.25));
+}
+
+#[test]
+fn table_round_trip() {
+    let mut table = Table::default();
+    table.set_pitch(61, I16F16::from_num(60.9));
+    table.set_pitch(127, I16F16::from_bits((126 << 16) | 0xfffc));
+    let mut writer = Writer::default();
+    table.save(&mut writer);
+    assert_eq!(writer.as_bytes().unwrap().len(), 3 * 128);
+
+    let mut loaded = Table::default();
+    loaded.load(&mut Reader::new(writer.as_bytes().unwrap())).unwrap();
+    for note in 0..128 {
+        assert_eq!(loaded.pitch(note), table.pitch(note));
+    }
+
+    let tuning = Tuning { edo: 22, ..tuning(Temperament::Edo, 7) };
+    let mut writer = Writer::default();
+    tuning.save(&mut writer);
+    assert_eq!(Tuning::load(&mut Reader::new(writer.as_bytes().unwrap())), Some(tuning));
+}